@@ -0,0 +1,93 @@
+/*
+ * This file is part of discord-presence. Extension for Zed that adds support for Discord Rich Presence using LSP.
+ *
+ * Copyright (c) 2024 Steinhübl
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>
+ */
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The local day of the week and time of day, passed in so schedules can be tested.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    pub weekday: u8,  // 0 is Sunday
+    pub minutes: u16, // since midnight
+}
+
+impl LocalTime {
+    pub fn is_weekend(self) -> bool {
+        matches!(self.weekday, 0 | 6)
+    }
+
+    fn from_utc(secs: u64) -> Self {
+        // 1970-01-01 was a Thursday
+        let days = secs / 86_400;
+
+        Self {
+            weekday: ((days + 4) % 7) as u8,
+            minutes: (secs % 86_400 / 60) as u16,
+        }
+    }
+}
+
+/// Returns the current local time, falling back to UTC when the time zone can't be read.
+#[cfg(unix)]
+pub fn now() -> LocalTime {
+    let mut tm = std::mem::MaybeUninit::<libc::tm>::zeroed();
+
+    // SAFETY: `localtime_r` only writes to `tm` and returns null on failure
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        if libc::localtime_r(&now, tm.as_mut_ptr()).is_null() {
+            return utc_now();
+        }
+        tm.assume_init()
+    };
+
+    LocalTime {
+        weekday: tm.tm_wday as u8,
+        minutes: (tm.tm_hour * 60 + tm.tm_min) as u16,
+    }
+}
+
+/// Returns the current time in UTC, the local time zone isn't read on this platform.
+#[cfg(not(unix))]
+pub fn now() -> LocalTime {
+    utc_now()
+}
+
+fn utc_now() -> LocalTime {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    LocalTime::from_utc(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_utc() {
+        // Saturday, 2024-08-03 13:45:00 UTC
+        let time = LocalTime::from_utc(1_722_692_700);
+
+        assert_eq!(time.weekday, 6);
+        assert_eq!(time.minutes, 13 * 60 + 45);
+        assert!(time.is_weekend());
+        assert!(!LocalTime::from_utc(0).is_weekend());
+    }
+}
@@ -17,18 +17,67 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>
  */
 
+use std::error::Error;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fmt, fs};
+
+use serde::{Serialize, Serializer};
 use serde_json::Value;
+use tracing::warn;
 
-#[derive(Debug, PartialEq)]
+use crate::clock::LocalTime;
+use crate::git::get_host;
+use crate::util::{
+    expand_env, expand_home, expand_path, glob_matches, relative_path, resolve_symlinks,
+};
+
+const ENV_PREFIX: &str = "DISCORD_PRESENCE_";
+const DEFAULT_BASE_ICONS_URL: &str =
+    "https://raw.githubusercontent.com/xhyrom/zed-discord-presence/main/assets/icons";
+
+macro_rules! set_option {
+    ($target:expr, $options:ident, $field:ident, $key:expr) => {
+        if let Some(value) = $options.get($key) {
+            $target.$field = if value.is_null() {
+                None
+            } else {
+                Some(value.as_str().unwrap().to_string())
+            };
+        }
+    };
+}
+
+macro_rules! set_string {
+    ($self:ident, $options:ident, $field:ident, $key:expr) => {
+        if let Some(value) = $options.get($key) {
+            $self.$field = value.as_str().unwrap().to_string();
+        }
+    };
+}
+
+macro_rules! env_option {
+    ($self:ident.$($field:ident).+, $key:expr) => {
+        if let Some(value) = env_var($key) {
+            $self.$($field).+ = if value.is_empty() { None } else { Some(value) };
+        }
+    };
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RulesMode {
     Whitelist,
     Blacklist,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Rules {
     pub mode: RulesMode,
     pub paths: Vec<String>,
+    pub remotes: Vec<String>, // host patterns or substrings of the git remote url
+    pub languages: Vec<String>, // checked per document
+    pub respect_gitignore: bool, // hides documents ignored by git regardless of the mode
 }
 
 impl Default for Rules {
@@ -36,13 +85,84 @@ impl Default for Rules {
         Rules {
             mode: RulesMode::Blacklist,
             paths: Vec::new(),
+            remotes: Vec::new(),
+            languages: Vec::new(),
+            respect_gitignore: false,
+        }
+    }
+}
+
+impl RulesMode {
+    fn parse(mode: &str) -> Self {
+        match mode {
+            "whitelist" => RulesMode::Whitelist,
+            "blacklist" => RulesMode::Blacklist,
+            _ => RulesMode::Blacklist,
         }
     }
 }
 
 impl Rules {
-    pub fn suitable(&self, path: &str) -> bool {
-        let contains = self.paths.contains(&path.to_string());
+    /// Whether the workspace is shown, checked against the absolute `paths` and the `remotes`.
+    pub fn suitable(&self, path: &str, remote_url: Option<&str>) -> bool {
+        let mut absolute = self.paths.iter().filter(|p| !is_relative_pattern(p));
+
+        // Only relative paths decide per document, the workspace itself is allowed
+        if self.mode == RulesMode::Whitelist
+            && absolute.clone().next().is_none()
+            && self.remotes.is_empty()
+            && self.paths.iter().any(|p| is_relative_pattern(p))
+        {
+            return true;
+        }
+
+        let contains = absolute.any(|pattern| glob_matches(pattern, path))
+            || remote_url.is_some_and(|url| {
+                self.remotes
+                    .iter()
+                    .any(|pattern| remote_matches(pattern, url))
+            });
+
+        if self.mode == RulesMode::Blacklist {
+            !contains
+        } else {
+            contains
+        }
+    }
+
+    /// Whether a document is shown by the relative `paths`, matched against its path inside
+    /// the workspace (`None` outside of it). Every document is when there are none.
+    pub fn allows_document(&self, relative_path: Option<&str>) -> bool {
+        let mut relative = self
+            .paths
+            .iter()
+            .filter(|p| is_relative_pattern(p))
+            .peekable();
+        if relative.peek().is_none() {
+            return true;
+        }
+
+        let contains = relative_path
+            .is_some_and(|path| relative.any(|pattern| relative_pattern_matches(pattern, path)));
+
+        if self.mode == RulesMode::Blacklist {
+            !contains
+        } else {
+            contains
+        }
+    }
+
+    /// Whether a document in the given language should be shown, every language is
+    /// allowed when no `languages` are set.
+    pub fn allows_language(&self, language: &str) -> bool {
+        if self.languages.is_empty() {
+            return true;
+        }
+
+        let contains = self
+            .languages
+            .iter()
+            .any(|l| l.eq_ignore_ascii_case(language));
 
         if self.mode == RulesMode::Blacklist {
             !contains
@@ -52,16 +172,74 @@ impl Rules {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// Matches a path inside the workspace, like in `.gitignore` a name without a separator
+/// matches at any depth.
+fn relative_pattern_matches(pattern: &str, path: &str) -> bool {
+    if pattern.contains('/') {
+        glob_matches(pattern, path)
+    } else {
+        glob_matches(&format!("**/{pattern}"), path)
+    }
+}
+
+/// Paths starting with `/`, `~` or a drive letter are absolute, anything else is matched
+/// relative to the workspace.
+fn is_relative_pattern(pattern: &str) -> bool {
+    !(pattern.starts_with(['/', '~']) || Path::new(pattern).is_absolute())
+}
+
+/// Matches a remote url by its host (see `host_matches`) or by a substring of the whole url.
+fn remote_matches(pattern: &str, url: &str) -> bool {
+    get_host(url).is_some_and(|host| host_matches(pattern, host))
+        || url.to_lowercase().contains(&pattern.to_lowercase())
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum IdleAction {
     ClearActivity,  // Clear the activity
     ChangeActivity, // Change the activity
 }
 
-#[derive(Debug)]
+impl IdleAction {
+    fn parse(action: &str) -> Self {
+        match action {
+            "clear_activity" => IdleAction::ClearActivity,
+            "change_activity" => IdleAction::ChangeActivity,
+            _ => IdleAction::ChangeActivity,
+        }
+    }
+}
+
+/// How the idle `variants` are picked each time the editor goes idle
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleRotate {
+    RoundRobin, // in order, starting over after the last one
+    Random,
+}
+
+impl IdleRotate {
+    fn parse(rotate: &str) -> Self {
+        match rotate {
+            "random" => IdleRotate::Random,
+            _ => IdleRotate::RoundRobin,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct Idle {
-    pub timeout: u64,       // in seconds
-    pub action: IdleAction, // what to do when idle
+    pub enabled: bool,                  // never go idle when disabled
+    pub timeout: u64,                   // in seconds
+    pub action: IdleAction,             // what to do when idle
+    pub keep_language: bool,            // resolve placeholders with the last document
+    pub on_screen_lock: bool,           // go idle when the screen gets locked
+    pub on_focus_loss: bool,            // go idle when the client reports Zed lost focus
+    pub application_id: Option<String>, // switches to another Discord application while idle
+    pub active_languages: Vec<String>,  // languages or `.ext` extensions that reset the timeout
+    pub rotate: IdleRotate,
+    pub variants: Vec<ActivityOverride>, // unset fields fall back to the ones below
 
     pub state: Option<String>,
     pub details: Option<String>,
@@ -75,8 +253,16 @@ pub struct Idle {
 impl Default for Idle {
     fn default() -> Self {
         Idle {
+            enabled: true,
             timeout: 300,
             action: IdleAction::ChangeActivity,
+            keep_language: false,
+            on_screen_lock: false,
+            on_focus_loss: false,
+            application_id: None,
+            active_languages: Vec::new(),
+            rotate: IdleRotate::RoundRobin,
+            variants: Vec::new(),
 
             state: Some("Idling".to_string()),
             details: Some("In Zed".to_string()),
@@ -89,11 +275,137 @@ impl Default for Idle {
     }
 }
 
-#[derive(Debug)]
-pub struct Configuration {
-    pub application_id: String,
-    pub base_icons_url: String,
+/// Which document events update the presence, each mode also updates on the later ones.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateOn {
+    Change, // Every edit, save and open
+    Save,   // Saves and opens
+    Open,   // Opens only
+}
+
+impl UpdateOn {
+    fn parse(event: &str) -> Self {
+        match event {
+            "change" => UpdateOn::Change,
+            "save" => UpdateOn::Save,
+            "open" => UpdateOn::Open,
+            _ => UpdateOn::Change,
+        }
+    }
+
+    pub fn accepts(self, event: UpdateOn) -> bool {
+        event >= self
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownEnvVars {
+    Keep,  // `$VAR` stays as written
+    Empty, // `$VAR` is removed
+}
+
+impl UnknownEnvVars {
+    fn parse(mode: &str) -> Self {
+        match mode {
+            "keep" => UnknownEnvVars::Keep,
+            "empty" => UnknownEnvVars::Empty,
+            _ => UnknownEnvVars::Keep,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyMode {
+    Full,         // Show everything the templates contain
+    LanguageOnly, // Show only the language and generic text
+    Hidden,       // Clear the activity
+}
+
+impl PrivacyMode {
+    fn parse(mode: &str) -> Self {
+        match mode {
+            "full" => PrivacyMode::Full,
+            "language_only" => PrivacyMode::LanguageOnly,
+            "hidden" => PrivacyMode::Hidden,
+            _ => PrivacyMode::Full,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Privacy {
+    pub mode: PrivacyMode,
+}
+
+impl Default for Privacy {
+    fn default() -> Self {
+        Privacy {
+            mode: PrivacyMode::Full,
+        }
+    }
+}
+
+impl Idle {
+    /// The variant shown the `nth` time the editor goes idle, `None` without `variants`.
+    pub fn variant(&self, nth: usize) -> Option<&ActivityOverride> {
+        let index = match self.rotate {
+            IdleRotate::RoundRobin => nth.checked_rem(self.variants.len())?,
+            // Doesn't need to be more random than the time it went idle
+            IdleRotate::Random => {
+                let nanos = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |duration| duration.subsec_nanos());
+                (nanos as usize).checked_rem(self.variants.len())?
+            }
+        };
+
+        self.variants.get(index)
+    }
+
+    /// Whether editing a file resets the timeout, every file does when no `active_languages`
+    /// are set. Entries starting with a `.` match the extension instead of the language.
+    pub fn counts(&self, language: &str, extension: &str) -> bool {
+        self.active_languages.is_empty()
+            || self
+                .active_languages
+                .iter()
+                .any(|entry| match entry.strip_prefix('.') {
+                    Some(entry) => entry.eq_ignore_ascii_case(extension),
+                    None => entry.eq_ignore_ascii_case(language),
+                })
+    }
+}
+
+/// Party shown as e.g. "1 of 3" in Discord
+#[derive(Debug, Default, Serialize)]
+pub struct Party {
+    pub size: Option<u32>,
+    pub max: Option<u32>,
+}
+
+impl Party {
+    /// The current and maximum size, `None` unless both are set and the size fits
+    pub fn size(&self) -> Option<[u32; 2]> {
+        match (self.size, self.max) {
+            (Some(size), Some(max)) if size > 0 && size <= max => Some([size, max]),
+            _ => None,
+        }
+    }
+}
+
+/// How `{workspace}` is named
+#[derive(Debug, Default, Serialize)]
+pub struct WorkspaceName {
+    pub from_git: bool, // use the repository name of the git remote
+}
 
+/// Templates replacing the regular ones in a collaboration session or while a schedule entry
+/// applies, unset fields keep them.
+#[derive(Debug, Default, Serialize)]
+pub struct ActivityOverride {
     pub state: Option<String>,
     pub details: Option<String>,
 
@@ -101,108 +413,2155 @@ pub struct Configuration {
     pub large_text: Option<String>,
     pub small_image: Option<String>,
     pub small_text: Option<String>,
+}
 
-    pub rules: Rules,
+impl ActivityOverride {
+    fn parse(options: &Value) -> Self {
+        let mut activity = ActivityOverride::default();
 
-    pub idle: Idle,
+        set_option!(activity, options, state, "state");
+        set_option!(activity, options, details, "details");
+        set_option!(activity, options, large_image, "large_image");
+        set_option!(activity, options, large_text, "large_text");
+        set_option!(activity, options, small_image, "small_image");
+        set_option!(activity, options, small_text, "small_text");
 
-    pub git_integration: bool,
+        activity
+    }
 }
 
-macro_rules! set_option {
-    ($self:ident, $options:ident, $field:ident, $key:expr) => {
-        if let Some(value) = $options.get($key) {
-            $self.$field = if value.is_null() {
-                None
-            } else {
-                Some(value.as_str().unwrap().to_string())
-            };
+/// A time range like `22:00`-`06:00`, which may wrap around midnight
+#[derive(Debug, Serialize)]
+pub struct Hours {
+    #[serde(serialize_with = "serialize_time")]
+    pub from: u16, // minutes since midnight
+    #[serde(serialize_with = "serialize_time")]
+    pub to: u16,
+    #[serde(flatten)]
+    pub activity: ActivityOverride,
+}
+
+impl Hours {
+    fn parse(options: &Value) -> Option<Self> {
+        let time = |key| {
+            options
+                .get(key)
+                .and_then(Value::as_str)
+                .and_then(parse_time)
+        };
+
+        Some(Hours {
+            from: time("from")?,
+            to: time("to")?,
+            activity: ActivityOverride::parse(options),
+        })
+    }
+
+    fn contains(&self, minutes: u16) -> bool {
+        in_range(self.from, self.to, minutes)
+    }
+}
+
+/// Whether the minutes since midnight are in `from`-`to`, the range wraps around midnight when
+/// `to` is earlier
+fn in_range(from: u16, to: u16, minutes: u16) -> bool {
+    if from <= to {
+        (from..to).contains(&minutes)
+    } else {
+        minutes >= from || minutes < to
+    }
+}
+
+/// Activity overrides by the local time, checked whenever the activity is updated.
+#[derive(Debug, Default, Serialize)]
+pub struct Schedule {
+    pub weekend: Option<ActivityOverride>,
+    pub hours: Vec<Hours>,
+}
+
+impl Schedule {
+    /// The first `hours` entry containing the time, then `weekend` on Saturdays and Sundays.
+    pub fn active(&self, time: LocalTime) -> Option<&ActivityOverride> {
+        self.hours
+            .iter()
+            .find(|hours| hours.contains(time.minutes))
+            .map(|hours| &hours.activity)
+            .or_else(|| self.weekend.as_ref().filter(|_| time.is_weekend()))
+    }
+}
+
+/// A time range where the presence is cleared, on every day unless `days` is set
+#[derive(Debug, Serialize)]
+pub struct DndHours {
+    #[serde(serialize_with = "serialize_time")]
+    pub from: u16, // minutes since midnight
+    #[serde(serialize_with = "serialize_time")]
+    pub to: u16,
+    #[serde(serialize_with = "serialize_days")]
+    pub days: Vec<u8>, // 0 is Sunday, a range wrapping around midnight belongs to the day it starts on
+}
+
+impl DndHours {
+    fn parse(options: &Value) -> Option<Self> {
+        let time = |key| {
+            options
+                .get(key)
+                .and_then(Value::as_str)
+                .and_then(parse_time)
+        };
+        let days = match options.get("days") {
+            Some(Value::Array(days)) => days
+                .iter()
+                .map(|day| day.as_str().and_then(parse_weekday))
+                .collect::<Option<_>>()?,
+            _ => Vec::new(),
+        };
+
+        Some(DndHours {
+            from: time("from")?,
+            to: time("to")?,
+            days,
+        })
+    }
+
+    fn contains(&self, time: LocalTime) -> bool {
+        if !in_range(self.from, self.to, time.minutes) {
+            return false;
         }
-    };
+
+        // After midnight, a wrapping range started the day before
+        let day = if self.from > self.to && time.minutes < self.to {
+            (time.weekday + 6) % 7
+        } else {
+            time.weekday
+        };
+
+        self.days.is_empty() || self.days.contains(&day)
+    }
 }
 
-macro_rules! set_string {
-    ($self:ident, $options:ident, $field:ident, $key:expr) => {
-        if let Some(value) = $options.get($key) {
-            $self.$field = value.as_str().unwrap().to_string();
+/// "Do not disturb" hours where the presence is cleared, checked whenever the activity is updated.
+#[derive(Debug, Default, Serialize)]
+pub struct Dnd {
+    pub hours: Vec<DndHours>,
+}
+
+impl Dnd {
+    pub fn active(&self, time: LocalTime) -> bool {
+        self.hours.iter().any(|hours| hours.contains(time))
+    }
+}
+
+const WEEKDAYS: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+/// Parses a day like `mon` or `Monday`, 0 is Sunday
+fn parse_weekday(day: &str) -> Option<u8> {
+    let day = day.to_lowercase();
+    if day.len() < 3 {
+        return None;
+    }
+
+    WEEKDAYS
+        .iter()
+        .position(|weekday| weekday.starts_with(&day))
+        .and_then(|weekday| u8::try_from(weekday).ok())
+}
+
+/// Parses `HH:MM` into minutes since midnight
+fn parse_time(time: &str) -> Option<u16> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// Reads seconds from a number or a duration string, `None` for null or an invalid value
+fn timeout_secs(value: &Value) -> Option<u64> {
+    match value {
+        Value::Null => Some(300),
+        Value::String(duration) => parse_duration(duration),
+        _ => value.as_u64(),
+    }
+}
+
+/// Parses seconds like `600` or a duration made of `h`, `m` and `s` parts like `15m` or `1h30m`
+fn parse_duration(duration: &str) -> Option<u64> {
+    let duration = duration.trim();
+    if let Ok(secs) = duration.parse() {
+        return Some(secs);
+    }
+
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in duration.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
         }
-    };
+
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let amount: u64 = digits.parse().ok()?;
+        total = total.checked_add(amount.checked_mul(unit)?)?;
+        digits.clear();
+    }
+
+    // A trailing number without a unit is ambiguous, e.g. `1h30`
+    digits.is_empty().then_some(total)
 }
 
-impl Configuration {
-    pub fn new() -> Self {
-        Self {
-            application_id: String::from("1263505205522337886"),
-            base_icons_url: String::from(
-                "https://raw.githubusercontent.com/xhyrom/zed-discord-presence/main/assets/icons/",
-            ),
-            state: Some(String::from("Working on {filename}")),
-            details: Some(String::from("In {workspace}")),
-            large_image: Some(String::from("{base_icons_url}/{language}.png")),
-            large_text: Some(String::from("{language:u}")),
-            small_image: Some(String::from("{base_icons_url}/zed.png")),
-            small_text: Some(String::from("Zed")),
-            rules: Rules::default(),
-            idle: Idle::default(),
-            git_integration: true,
+#[derive(Debug, Serialize)]
+pub struct Git {
+    pub hide_private: bool,
+    pub public_hosts: Vec<String>,
+    pub private_hosts: Vec<String>,
+    pub button_label: String,
+}
+
+impl Default for Git {
+    fn default() -> Self {
+        Git {
+            hide_private: false,
+            public_hosts: vec![
+                String::from("github.com"),
+                String::from("gitlab.com"),
+                String::from("codeberg.org"),
+                String::from("bitbucket.org"),
+            ],
+            private_hosts: Vec::new(),
+            button_label: String::from("View Repository"),
         }
     }
+}
 
-    pub fn set(&mut self, initialization_options: Option<Value>) {
-        if let Some(options) = initialization_options {
-            set_string!(self, options, application_id, "application_id");
-            set_string!(self, options, base_icons_url, "base_icons_url");
-            set_option!(self, options, state, "state");
-            set_option!(self, options, details, "details");
-            set_option!(self, options, large_image, "large_image");
-            set_option!(self, options, large_text, "large_text");
-            set_option!(self, options, small_image, "small_image");
-            set_option!(self, options, small_text, "small_text");
-
-            if let Some(rules) = options.get("rules") {
-                self.rules.mode = rules.get("mode").and_then(|m| m.as_str()).map_or(
-                    RulesMode::Blacklist,
-                    |mode| match mode {
-                        "whitelist" => RulesMode::Whitelist,
-                        "blacklist" => RulesMode::Blacklist,
-                        _ => RulesMode::Blacklist,
-                    },
-                );
-
-                self.rules.paths =
-                    rules
-                        .get("paths")
-                        .and_then(|p| p.as_array())
-                        .map_or(Vec::new(), |paths| {
-                            paths
-                                .iter()
-                                .filter_map(|p| p.as_str().map(|s| s.to_string()))
-                                .collect()
-                        });
-            }
+impl Git {
+    /// Whether the repository should be hidden, git can't know its visibility so
+    /// remotes on a private host or on a host that isn't public are considered private.
+    pub fn hides(&self, remote_url: &str) -> bool {
+        if !self.hide_private {
+            return false;
+        }
 
-            if let Some(idle) = options.get("idle") {
-                self.idle.timeout = idle.get("timeout").and_then(|t| t.as_u64()).unwrap_or(300);
-                self.idle.action = idle.get("action").and_then(|a| a.as_str()).map_or(
-                    IdleAction::ChangeActivity,
-                    |action| match action {
-                        "clear_activity" => IdleAction::ClearActivity,
-                        "change_activity" => IdleAction::ChangeActivity,
-                        _ => IdleAction::ChangeActivity,
-                    },
-                );
-
-                set_option!(self, idle, state, "state");
-                set_option!(self, idle, details, "details");
-                set_option!(self, idle, large_image, "large_image");
-                set_option!(self, idle, large_text, "large_text");
-                set_option!(self, idle, small_image, "small_image");
-                set_option!(self, idle, small_text, "small_text");
-            }
+        let Some(host) = get_host(remote_url) else {
+            return true;
+        };
 
-            if let Some(git_integration) = options.get("git_integration") {
-                self.git_integration = git_integration.as_bool().unwrap_or(true);
-            }
+        let matches = |patterns: &[String]| patterns.iter().any(|p| host_matches(p, host));
+        matches(&self.private_hosts) || !matches(&self.public_hosts)
+    }
+}
+
+/// Matches a host against a pattern where a leading `*.` matches any subdomain.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let (pattern, host) = (pattern.to_lowercase(), host.to_lowercase());
+
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampMode {
+    Session,   // One start for the session, kept across restarts with `persist`
+    Workspace, // One start per workspace, always kept across restarts
+    Language,  // Like `Session`, but restarts when switching to another language
+}
+
+impl TimestampMode {
+    fn parse(mode: &str) -> Self {
+        match mode {
+            "session" => TimestampMode::Session,
+            "workspace" => TimestampMode::Workspace,
+            "language" => TimestampMode::Language,
+            _ => TimestampMode::Session,
+        }
+    }
+}
+
+/// Where the card timestamp or `{elapsed}` start, instead of where `mode` does
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSource {
+    Session,   // When Zed was opened, kept across restarts with `persist`
+    Workspace, // When the workspace was first opened, kept across restarts
+    File,      // When the current file was switched to
+}
+
+impl TimestampSource {
+    fn parse(source: &str) -> Option<Self> {
+        match source {
+            "session" => Some(TimestampSource::Session),
+            "workspace" => Some(TimestampSource::Workspace),
+            "file" => Some(TimestampSource::File),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Timestamp {
+    pub mode: TimestampMode,
+    pub end_in_secs: Option<u64>, // countdown instead of elapsed time
+    pub persist: bool,            // keep the start across restarts
+    pub grace_secs: u64,          // how long after the last session the start is kept
+    pub card_source: Option<TimestampSource>, // `mode` decides when unset
+    pub elapsed_source: Option<TimestampSource>, // for `{elapsed}`, the card's when unset
+}
+
+impl Default for Timestamp {
+    fn default() -> Self {
+        Timestamp {
+            mode: TimestampMode::Session,
+            end_in_secs: None,
+            persist: false,
+            grace_secs: 600,
+            card_source: None,
+            elapsed_source: None,
+        }
+    }
+}
+
+impl Timestamp {
+    /// The source of `{elapsed}`, `None` when it follows `mode` like the card.
+    pub fn elapsed_source(&self) -> Option<TimestampSource> {
+        self.elapsed_source.or(self.card_source)
+    }
+
+    /// Whether the workspace start is needed by a source although `mode` doesn't keep it.
+    pub fn needs_workspace_start(&self) -> bool {
+        self.mode != TimestampMode::Workspace
+            && [self.card_source, self.elapsed_source].contains(&Some(TimestampSource::Workspace))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Connection {
+    pub heartbeat_secs: u64, // 0 disables the heartbeat
+    pub lazy: bool,          // connects with the first document instead of on startup
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Connection {
+            heartbeat_secs: 60,
+            lazy: false,
         }
     }
 }
+
+/// Publishes the presence to other tools, read on startup.
+#[derive(Debug, Default, Serialize)]
+pub struct Events {
+    pub socket_path: Option<String>, // Unix socket or named pipe, nothing is published without it
+}
+
+/// A field whose value has the wrong type, e.g. `idle.timeout must be a whole number`
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    pub field: String,
+    pub expected: &'static str,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} must be {}", self.field, self.expected)
+    }
+}
+
+impl Error for ConfigError {}
+
+#[derive(Debug, Serialize)]
+pub struct Configuration {
+    pub application_id: String,
+    pub base_icons_url: String,
+
+    pub state: Option<String>,
+    pub details: Option<String>,
+
+    // Used instead of `state`/`details` depending on whether a repository was detected
+    pub git_state: Option<String>,
+    pub git_details: Option<String>,
+    pub no_git_state: Option<String>,
+    pub no_git_details: Option<String>,
+
+    pub large_image: Option<String>,
+    pub large_text: Option<String>,
+    pub small_image: Option<String>,
+    pub small_text: Option<String>,
+
+    pub fallback_image: Option<String>,
+    pub status_small_image: bool, // replaces the small image with the connection or idle status
+    pub dirty_small_image: Option<String>, // while the active document has unsaved changes
+    pub default_language: String, // what unknown files resolve to
+
+    pub show_large_image: bool,
+    pub show_small_image: bool,
+    pub swap_images: bool,
+    pub use_asset_keys: bool, // images under `base_icons_url` become keys of the application's assets
+
+    pub hide_file_extension: bool,
+    pub max_filename_length: Option<usize>, // `{filename}` is truncated in the middle
+
+    pub workspace_name: WorkspaceName,
+
+    pub only_workspace_files: bool,
+    pub resolve_symlinks: bool, // compares the real paths of documents and the workspace
+
+    pub min_update_interval_secs: u64, // Discord rate limits activity updates
+    pub update_on: UpdateOn,
+    pub startup_grace_ms: u64, // only the last update in this time after startup is sent
+
+    pub rules: Rules,
+
+    pub idle: Idle,
+
+    pub collab: ActivityOverride, // shown while in a collaboration session
+
+    pub schedule: Schedule,
+
+    pub dnd: Dnd,
+
+    #[serde(serialize_with = "serialize_pairs")]
+    pub extensions: Vec<(String, ActivityOverride)>, // by file extension, without the dot
+
+    #[serde(serialize_with = "serialize_pairs")]
+    pub language_by_path: Vec<(String, String)>, // relative path globs and the language they force
+
+    pub party: Party,
+
+    pub privacy: Privacy,
+
+    pub timestamp: Timestamp,
+
+    pub connection: Connection,
+
+    pub events: Events,
+
+    pub git_integration: bool,
+    pub git: Git,
+
+    pub unknown_env_vars: UnknownEnvVars, // for `$VAR` in string options
+
+    #[serde(serialize_with = "serialize_pairs")]
+    pub profiles: Vec<(String, Value)>, // workspace path globs and the options they overlay
+    #[serde(skip)]
+    workspace_path: String,
+}
+
+impl Configuration {
+    pub fn new() -> Self {
+        Self {
+            application_id: String::from("1263505205522337886"),
+            base_icons_url: String::from(DEFAULT_BASE_ICONS_URL),
+            state: Some(String::from("Working on {filename}")),
+            details: Some(String::from("In {workspace}")),
+            git_state: None,
+            git_details: None,
+            no_git_state: None,
+            no_git_details: None,
+            large_image: Some(String::from("{base_icons_url}/{language}.png")),
+            large_text: Some(String::from("{language:u}")),
+            small_image: Some(String::from("{base_icons_url}/zed.png")),
+            small_text: Some(String::from("Zed")),
+            fallback_image: None,
+            status_small_image: false,
+            dirty_small_image: None,
+            default_language: String::from("text"),
+            show_large_image: true,
+            show_small_image: true,
+            swap_images: false,
+            use_asset_keys: false,
+            hide_file_extension: false,
+            max_filename_length: None,
+            workspace_name: WorkspaceName::default(),
+            only_workspace_files: false,
+            resolve_symlinks: false,
+            min_update_interval_secs: 15,
+            update_on: UpdateOn::Change,
+            startup_grace_ms: 0,
+            rules: Rules::default(),
+            idle: Idle::default(),
+            collab: ActivityOverride {
+                details: Some(String::from("Pair programming in {workspace}")),
+                ..Default::default()
+            },
+            schedule: Schedule::default(),
+            dnd: Dnd::default(),
+            extensions: Vec::new(),
+            language_by_path: Vec::new(),
+            party: Party::default(),
+            privacy: Privacy::default(),
+            timestamp: Timestamp::default(),
+            connection: Connection::default(),
+            events: Events::default(),
+            git_integration: true,
+            git: Git::default(),
+            unknown_env_vars: UnknownEnvVars::Keep,
+            profiles: Vec::new(),
+            workspace_path: String::new(),
+        }
+    }
+
+    /// The `extensions` override for a file, dotfiles like `.env` are matched by their name.
+    pub fn extension_override(&self, filename: &str) -> Option<&ActivityOverride> {
+        let extension = match Path::new(filename).extension() {
+            Some(extension) => extension.to_string_lossy().to_lowercase(),
+            None => filename.strip_prefix('.')?.to_lowercase(),
+        };
+
+        self.extensions
+            .iter()
+            .find(|(key, _)| *key == extension)
+            .map(|(_, activity)| activity)
+    }
+
+    /// The `language_by_path` language of a document, from the first glob matching its path
+    /// inside the workspace.
+    pub fn language_override(&self, path: &Path) -> Option<&str> {
+        let relative = relative_path(path, Path::new(&self.workspace_path))?;
+
+        self.language_by_path
+            .iter()
+            .find(|(pattern, _)| relative_pattern_matches(pattern, &relative))
+            .map(|(_, language)| language.as_str())
+    }
+
+    /// The `state` for when a repository was (or wasn't) detected, defaulting to `state`.
+    pub fn state_for(&self, has_git: bool) -> &Option<String> {
+        let state = if has_git {
+            &self.git_state
+        } else {
+            &self.no_git_state
+        };
+
+        if state.is_some() {
+            state
+        } else {
+            &self.state
+        }
+    }
+
+    /// The `details` for when a repository was (or wasn't) detected, defaulting to `details`.
+    pub fn details_for(&self, has_git: bool) -> &Option<String> {
+        let details = if has_git {
+            &self.git_details
+        } else {
+            &self.no_git_details
+        };
+
+        if details.is_some() {
+            details
+        } else {
+            &self.details
+        }
+    }
+
+    /// Applies the options, nothing is changed if a field has the wrong type.
+    pub fn set(&mut self, initialization_options: Option<Value>) -> Result<(), ConfigError> {
+        // The config file is merged after the initialization options
+        let mut sources: Vec<Value> = initialization_options
+            .map(unwrap_envelope)
+            .into_iter()
+            .chain(config_file().map(unwrap_envelope))
+            .filter_map(object_options)
+            .collect();
+
+        // Decided up front, as every source is expanded before any of them is applied
+        let unknown_env_vars = env_var("UNKNOWN_ENV_VARS")
+            .or_else(|| {
+                sources
+                    .iter()
+                    .rev()
+                    .find_map(|options| options.get("unknown_env_vars")?.as_str().map(String::from))
+            })
+            .map_or(self.unknown_env_vars == UnknownEnvVars::Keep, |mode| {
+                UnknownEnvVars::parse(&mode) == UnknownEnvVars::Keep
+            });
+
+        for options in &mut sources {
+            expand_env_values(options, unknown_env_vars);
+            validate(options, "")?;
+        }
+
+        for options in sources {
+            self.apply(&options);
+        }
+
+        // The first profile matching the workspace is layered over both
+        if let Some(profile) = self.matching_profile() {
+            self.apply(&profile);
+        }
+
+        self.apply_env();
+        self.normalize_base_icons_url();
+
+        if self.resolve_symlinks {
+            self.rules.paths = self
+                .rules
+                .paths
+                .iter()
+                .map(|path| {
+                    if is_relative_pattern(path) {
+                        return path.clone();
+                    }
+
+                    resolve_symlinks(Path::new(path))
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+        }
+
+        Ok(())
+    }
+
+    /// Sets the workspace that `profiles` are matched against.
+    pub fn set_workspace_path(&mut self, path: &str) {
+        self.workspace_path = path.to_string();
+    }
+
+    /// The overlay of the first profile whose glob matches the workspace path.
+    fn matching_profile(&self) -> Option<Value> {
+        self.profiles
+            .iter()
+            .find(|(pattern, _)| glob_matches(pattern, &self.workspace_path))
+            .map(|(_, overlay)| overlay.clone())
+    }
+
+    fn apply(&mut self, options: &Value) {
+        set_string!(self, options, application_id, "application_id");
+        set_string!(self, options, base_icons_url, "base_icons_url");
+        set_string!(self, options, default_language, "default_language");
+        set_option!(self, options, state, "state");
+        set_option!(self, options, details, "details");
+        set_option!(self, options, git_state, "git_state");
+        set_option!(self, options, git_details, "git_details");
+        set_option!(self, options, no_git_state, "no_git_state");
+        set_option!(self, options, no_git_details, "no_git_details");
+        set_option!(self, options, large_image, "large_image");
+        set_option!(self, options, large_text, "large_text");
+        set_option!(self, options, small_image, "small_image");
+        set_option!(self, options, small_text, "small_text");
+        set_option!(self, options, fallback_image, "fallback_image");
+        set_option!(self, options, dirty_small_image, "dirty_small_image");
+
+        if let Some(show_large_image) = options.get("show_large_image") {
+            self.show_large_image = show_large_image.as_bool().unwrap_or(true);
+        }
+
+        if let Some(show_small_image) = options.get("show_small_image") {
+            self.show_small_image = show_small_image.as_bool().unwrap_or(true);
+        }
+
+        if let Some(status_small_image) = options.get("status_small_image") {
+            self.status_small_image = status_small_image.as_bool().unwrap_or(false);
+        }
+
+        if let Some(swap_images) = options.get("swap_images") {
+            self.swap_images = swap_images.as_bool().unwrap_or(false);
+        }
+
+        if let Some(use_asset_keys) = options.get("use_asset_keys") {
+            self.use_asset_keys = use_asset_keys.as_bool().unwrap_or(false);
+        }
+
+        if let Some(hide_file_extension) = options.get("hide_file_extension") {
+            self.hide_file_extension = hide_file_extension.as_bool().unwrap_or(false);
+        }
+
+        if let Some(max_length) = options.get("max_filename_length") {
+            self.max_filename_length = max_length.as_u64().and_then(|m| m.try_into().ok());
+        }
+
+        if let Some(only_workspace_files) = options.get("only_workspace_files") {
+            self.only_workspace_files = only_workspace_files.as_bool().unwrap_or(false);
+        }
+
+        if let Some(resolve_symlinks) = options.get("resolve_symlinks") {
+            self.resolve_symlinks = resolve_symlinks.as_bool().unwrap_or(false);
+        }
+
+        if let Some(interval) = options.get("min_update_interval_secs") {
+            self.min_update_interval_secs = interval.as_u64().unwrap_or(15);
+        }
+
+        if let Some(update_on) = options.get("update_on") {
+            self.update_on = update_on.as_str().map_or(UpdateOn::Change, UpdateOn::parse);
+        }
+
+        if let Some(grace) = options.get("startup_grace_ms") {
+            self.startup_grace_ms = grace.as_u64().unwrap_or(0);
+        }
+
+        if let Some(rules) = options.get("rules") {
+            self.rules.mode = rules
+                .get("mode")
+                .and_then(|m| m.as_str())
+                .map_or(RulesMode::Blacklist, RulesMode::parse);
+
+            self.rules.paths = rules.get("paths").map_or(Vec::new(), |paths| {
+                // Variables were expanded with the other strings already
+                string_array(paths).iter().map(|p| expand_home(p)).collect()
+            });
+            self.rules.remotes = rules.get("remotes").map_or(Vec::new(), string_array);
+            self.rules.languages = rules.get("languages").map_or(Vec::new(), string_array);
+            self.rules.respect_gitignore = rules
+                .get("respect_gitignore")
+                .and_then(|r| r.as_bool())
+                .unwrap_or(false);
+        }
+
+        if let Some(idle) = options.get("idle") {
+            self.idle.enabled = idle
+                .get("enabled")
+                .and_then(|e| e.as_bool())
+                .unwrap_or(true);
+            self.idle.timeout = idle.get("timeout").map_or(300, |timeout| {
+                timeout_secs(timeout).unwrap_or_else(|| {
+                    warn!("Ignoring invalid idle.timeout, using the default: {timeout}");
+                    300
+                })
+            });
+            self.idle.action = idle
+                .get("action")
+                .and_then(|a| a.as_str())
+                .map_or(IdleAction::ChangeActivity, IdleAction::parse);
+            self.idle.keep_language = idle
+                .get("keep_language")
+                .and_then(|k| k.as_bool())
+                .unwrap_or(false);
+            self.idle.on_screen_lock = idle
+                .get("on_screen_lock")
+                .and_then(|o| o.as_bool())
+                .unwrap_or(false);
+            self.idle.on_focus_loss = idle
+                .get("on_focus_loss")
+                .and_then(|o| o.as_bool())
+                .unwrap_or(false);
+            self.idle.active_languages = idle
+                .get("active_languages")
+                .map_or(Vec::new(), string_array);
+            self.idle.rotate = idle
+                .get("rotate")
+                .and_then(|r| r.as_str())
+                .map_or(IdleRotate::RoundRobin, IdleRotate::parse);
+            self.idle.variants = idle
+                .get("variants")
+                .and_then(Value::as_array)
+                .map_or(Vec::new(), |variants| {
+                    variants.iter().map(ActivityOverride::parse).collect()
+                });
+
+            set_option!(self.idle, idle, application_id, "application_id");
+            set_option!(self.idle, idle, state, "state");
+            set_option!(self.idle, idle, details, "details");
+            set_option!(self.idle, idle, large_image, "large_image");
+            set_option!(self.idle, idle, large_text, "large_text");
+            set_option!(self.idle, idle, small_image, "small_image");
+            set_option!(self.idle, idle, small_text, "small_text");
+        }
+
+        if let Some(collab) = options.get("collab") {
+            set_option!(self.collab, collab, state, "state");
+            set_option!(self.collab, collab, details, "details");
+            set_option!(self.collab, collab, large_image, "large_image");
+            set_option!(self.collab, collab, large_text, "large_text");
+            set_option!(self.collab, collab, small_image, "small_image");
+            set_option!(self.collab, collab, small_text, "small_text");
+        }
+
+        if let Some(schedule) = options.get("schedule") {
+            self.schedule.weekend = schedule.get("weekend").map(ActivityOverride::parse);
+            self.schedule.hours = schedule
+                .get("hours")
+                .and_then(Value::as_array)
+                .map_or(Vec::new(), |hours| {
+                    hours
+                        .iter()
+                        .filter_map(|entry| {
+                            let hours = Hours::parse(entry);
+                            if hours.is_none() {
+                                warn!("Ignoring schedule hours without a valid `from` and `to`: {entry}");
+                            }
+                            hours
+                        })
+                        .collect()
+                });
+        }
+
+        if let Some(dnd) = options.get("dnd") {
+            self.dnd.hours = dnd
+                .get("hours")
+                .and_then(Value::as_array)
+                .map_or(Vec::new(), |hours| {
+                    hours
+                        .iter()
+                        .filter_map(|entry| {
+                            let hours = DndHours::parse(entry);
+                            if hours.is_none() {
+                                warn!("Ignoring dnd hours without a valid `from`, `to` and `days`: {entry}");
+                            }
+                            hours
+                        })
+                        .collect()
+                });
+        }
+
+        if let Some(workspace_name) = options.get("workspace_name") {
+            self.workspace_name.from_git = workspace_name
+                .get("from_git")
+                .and_then(|f| f.as_bool())
+                .unwrap_or(false);
+        }
+
+        if let Some(party) = options.get("party") {
+            let get = |key| party.get(key).and_then(Value::as_u64);
+            self.party.size = get("size").and_then(|s| s.try_into().ok());
+            self.party.max = get("max").and_then(|m| m.try_into().ok());
+        }
+
+        if let Some(privacy) = options.get("privacy") {
+            self.privacy.mode = privacy
+                .get("mode")
+                .and_then(|m| m.as_str())
+                .map_or(PrivacyMode::Full, PrivacyMode::parse);
+        }
+
+        if let Some(timestamp) = options.get("timestamp") {
+            self.timestamp.mode = timestamp
+                .get("mode")
+                .and_then(|m| m.as_str())
+                .map_or(TimestampMode::Session, TimestampMode::parse);
+            self.timestamp.end_in_secs = timestamp.get("end_in_secs").and_then(|e| e.as_u64());
+            self.timestamp.persist = timestamp
+                .get("persist")
+                .and_then(|p| p.as_bool())
+                .unwrap_or(false);
+            self.timestamp.grace_secs = timestamp
+                .get("grace_secs")
+                .and_then(|g| g.as_u64())
+                .unwrap_or(600);
+            self.timestamp.card_source = timestamp
+                .get("card_source")
+                .and_then(|c| c.as_str())
+                .and_then(TimestampSource::parse);
+            self.timestamp.elapsed_source = timestamp
+                .get("elapsed_source")
+                .and_then(|e| e.as_str())
+                .and_then(TimestampSource::parse);
+        }
+
+        if let Some(connection) = options.get("connection") {
+            self.connection.heartbeat_secs = connection
+                .get("heartbeat_secs")
+                .and_then(|h| h.as_u64())
+                .unwrap_or(60);
+            self.connection.lazy = connection
+                .get("lazy")
+                .and_then(|l| l.as_bool())
+                .unwrap_or(false);
+        }
+
+        if let Some(events) = options.get("events") {
+            set_option!(self.events, events, socket_path, "socket_path");
+        }
+
+        if let Some(git_integration) = options.get("git_integration") {
+            self.git_integration = git_integration.as_bool().unwrap_or(true);
+        }
+
+        if let Some(git) = options.get("git") {
+            self.git.hide_private = git
+                .get("hide_private")
+                .and_then(|h| h.as_bool())
+                .unwrap_or(false);
+
+            if let Some(public_hosts) = git.get("public_hosts") {
+                self.git.public_hosts = string_array(public_hosts);
+            }
+            if let Some(private_hosts) = git.get("private_hosts") {
+                self.git.private_hosts = string_array(private_hosts);
+            }
+            if let Some(button_label) = git.get("button_label").and_then(|b| b.as_str()) {
+                self.git.button_label = button_label.to_string();
+            }
+        }
+
+        if let Some(unknown_env_vars) = options.get("unknown_env_vars") {
+            self.unknown_env_vars = unknown_env_vars
+                .as_str()
+                .map_or(UnknownEnvVars::Keep, UnknownEnvVars::parse);
+        }
+
+        if let Some(extensions) = options.get("extensions") {
+            self.extensions = extensions.as_object().map_or(Vec::new(), |extensions| {
+                extensions
+                    .iter()
+                    .map(|(extension, activity)| {
+                        let extension = extension.strip_prefix('.').unwrap_or(extension);
+                        (extension.to_lowercase(), ActivityOverride::parse(activity))
+                    })
+                    .collect()
+            });
+        }
+
+        if let Some(language_by_path) = options.get("language_by_path") {
+            self.language_by_path =
+                language_by_path
+                    .as_object()
+                    .map_or(Vec::new(), |language_by_path| {
+                        language_by_path
+                            .iter()
+                            .filter_map(|(pattern, language)| {
+                                Some((pattern.clone(), language.as_str()?.to_string()))
+                            })
+                            .collect()
+                    });
+        }
+
+        if let Some(profiles) = options.get("profiles").and_then(Value::as_object) {
+            self.profiles = profiles
+                .iter()
+                .map(|(pattern, overlay)| (expand_path(pattern), overlay.clone()))
+                .collect();
+        }
+    }
+
+    /// Discord silently drops images without a scheme, so such a `base_icons_url` falls back
+    /// to the default. A trailing slash is removed as templates add their own.
+    fn normalize_base_icons_url(&mut self) {
+        let url = self.base_icons_url.trim();
+
+        if !["http://", "https://", "file://"]
+            .iter()
+            .any(|scheme| url.starts_with(scheme))
+        {
+            warn!("Ignoring base_icons_url without an http(s) or file scheme: {url}");
+            self.base_icons_url = String::from(DEFAULT_BASE_ICONS_URL);
+            return;
+        }
+
+        // Only a single slash, `file:///` must keep its path
+        self.base_icons_url = url.strip_suffix('/').unwrap_or(url).to_string();
+    }
+
+    /// Overrides fields with `DISCORD_PRESENCE_*` environment variables,
+    /// an empty value unsets optional fields.
+    fn apply_env(&mut self) {
+        if let Some(value) = env_var("APPLICATION_ID") {
+            self.application_id = value;
+        }
+        if let Some(value) = env_var("BASE_ICONS_URL") {
+            self.base_icons_url = value;
+        }
+        if let Some(value) = env_var("DEFAULT_LANGUAGE") {
+            self.default_language = value;
+        }
+
+        env_option!(self.state, "STATE");
+        env_option!(self.details, "DETAILS");
+        env_option!(self.git_state, "GIT_STATE");
+        env_option!(self.git_details, "GIT_DETAILS");
+        env_option!(self.no_git_state, "NO_GIT_STATE");
+        env_option!(self.no_git_details, "NO_GIT_DETAILS");
+        env_option!(self.large_image, "LARGE_IMAGE");
+        env_option!(self.large_text, "LARGE_TEXT");
+        env_option!(self.small_image, "SMALL_IMAGE");
+        env_option!(self.small_text, "SMALL_TEXT");
+        env_option!(self.fallback_image, "FALLBACK_IMAGE");
+        env_option!(self.dirty_small_image, "DIRTY_SMALL_IMAGE");
+
+        if let Some(show) = env_var("SHOW_LARGE_IMAGE").and_then(|s| parse_bool(&s)) {
+            self.show_large_image = show;
+        }
+        if let Some(show) = env_var("SHOW_SMALL_IMAGE").and_then(|s| parse_bool(&s)) {
+            self.show_small_image = show;
+        }
+
+        if let Some(status) = env_var("STATUS_SMALL_IMAGE").and_then(|s| parse_bool(&s)) {
+            self.status_small_image = status;
+        }
+
+        if let Some(swap) = env_var("SWAP_IMAGES").and_then(|s| parse_bool(&s)) {
+            self.swap_images = swap;
+        }
+
+        if let Some(keys) = env_var("USE_ASSET_KEYS").and_then(|k| parse_bool(&k)) {
+            self.use_asset_keys = keys;
+        }
+
+        if let Some(hide) = env_var("HIDE_FILE_EXTENSION").and_then(|h| parse_bool(&h)) {
+            self.hide_file_extension = hide;
+        }
+
+        if let Some(max_length) = env_var("MAX_FILENAME_LENGTH") {
+            self.max_filename_length = max_length.parse().ok();
+        }
+
+        if let Some(only) = env_var("ONLY_WORKSPACE_FILES").and_then(|o| parse_bool(&o)) {
+            self.only_workspace_files = only;
+        }
+
+        if let Some(resolve) = env_var("RESOLVE_SYMLINKS").and_then(|r| parse_bool(&r)) {
+            self.resolve_symlinks = resolve;
+        }
+
+        if let Some(interval) = env_var("MIN_UPDATE_INTERVAL_SECS").and_then(|i| i.parse().ok()) {
+            self.min_update_interval_secs = interval;
+        }
+
+        if let Some(update_on) = env_var("UPDATE_ON") {
+            self.update_on = UpdateOn::parse(&update_on);
+        }
+
+        if let Some(grace) = env_var("STARTUP_GRACE_MS").and_then(|g| g.parse().ok()) {
+            self.startup_grace_ms = grace;
+        }
+
+        if let Some(mode) = env_var("RULES_MODE") {
+            self.rules.mode = RulesMode::parse(&mode);
+        }
+        if let Some(paths) = env::var_os(format!("{ENV_PREFIX}RULES_PATHS")) {
+            self.rules.paths = env::split_paths(&paths)
+                .filter_map(|p| p.to_str().map(expand_path))
+                .collect();
+        }
+        if let Some(remotes) = env_var("RULES_REMOTES") {
+            self.rules.remotes = comma_separated(&remotes);
+        }
+        if let Some(languages) = env_var("RULES_LANGUAGES") {
+            self.rules.languages = comma_separated(&languages);
+        }
+        if let Some(respect) = env_var("RULES_RESPECT_GITIGNORE").and_then(|r| parse_bool(&r)) {
+            self.rules.respect_gitignore = respect;
+        }
+
+        if let Some(enabled) = env_var("IDLE_ENABLED").and_then(|e| parse_bool(&e)) {
+            self.idle.enabled = enabled;
+        }
+        if let Some(timeout) = env_var("IDLE_TIMEOUT").and_then(|t| parse_duration(&t)) {
+            self.idle.timeout = timeout;
+        }
+        if let Some(action) = env_var("IDLE_ACTION") {
+            self.idle.action = IdleAction::parse(&action);
+        }
+
+        if let Some(keep_language) = env_var("IDLE_KEEP_LANGUAGE").and_then(|k| parse_bool(&k)) {
+            self.idle.keep_language = keep_language;
+        }
+
+        if let Some(on_screen_lock) = env_var("IDLE_ON_SCREEN_LOCK").and_then(|o| parse_bool(&o)) {
+            self.idle.on_screen_lock = on_screen_lock;
+        }
+        if let Some(on_focus_loss) = env_var("IDLE_ON_FOCUS_LOSS").and_then(|o| parse_bool(&o)) {
+            self.idle.on_focus_loss = on_focus_loss;
+        }
+
+        if let Some(active_languages) = env_var("IDLE_ACTIVE_LANGUAGES") {
+            self.idle.active_languages = comma_separated(&active_languages);
+        }
+        if let Some(rotate) = env_var("IDLE_ROTATE") {
+            self.idle.rotate = IdleRotate::parse(&rotate);
+        }
+
+        env_option!(self.idle.application_id, "IDLE_APPLICATION_ID");
+        env_option!(self.idle.state, "IDLE_STATE");
+        env_option!(self.idle.details, "IDLE_DETAILS");
+        env_option!(self.idle.large_image, "IDLE_LARGE_IMAGE");
+        env_option!(self.idle.large_text, "IDLE_LARGE_TEXT");
+        env_option!(self.idle.small_image, "IDLE_SMALL_IMAGE");
+        env_option!(self.idle.small_text, "IDLE_SMALL_TEXT");
+
+        env_option!(self.collab.state, "COLLAB_STATE");
+        env_option!(self.collab.details, "COLLAB_DETAILS");
+        env_option!(self.collab.large_image, "COLLAB_LARGE_IMAGE");
+        env_option!(self.collab.large_text, "COLLAB_LARGE_TEXT");
+        env_option!(self.collab.small_image, "COLLAB_SMALL_IMAGE");
+        env_option!(self.collab.small_text, "COLLAB_SMALL_TEXT");
+
+        if let Some(from_git) = env_var("WORKSPACE_NAME_FROM_GIT").and_then(|f| parse_bool(&f)) {
+            self.workspace_name.from_git = from_git;
+        }
+
+        if let Some(size) = env_var("PARTY_SIZE") {
+            self.party.size = size.parse().ok();
+        }
+        if let Some(max) = env_var("PARTY_MAX") {
+            self.party.max = max.parse().ok();
+        }
+
+        if let Some(mode) = env_var("PRIVACY_MODE") {
+            self.privacy.mode = PrivacyMode::parse(&mode);
+        }
+
+        if let Some(mode) = env_var("TIMESTAMP_MODE") {
+            self.timestamp.mode = TimestampMode::parse(&mode);
+        }
+        if let Some(end_in_secs) = env_var("TIMESTAMP_END_IN_SECS") {
+            self.timestamp.end_in_secs = end_in_secs.parse().ok();
+        }
+        if let Some(persist) = env_var("TIMESTAMP_PERSIST").and_then(|p| parse_bool(&p)) {
+            self.timestamp.persist = persist;
+        }
+        if let Some(grace_secs) = env_var("TIMESTAMP_GRACE_SECS").and_then(|g| g.parse().ok()) {
+            self.timestamp.grace_secs = grace_secs;
+        }
+        if let Some(card_source) = env_var("TIMESTAMP_CARD_SOURCE") {
+            self.timestamp.card_source = TimestampSource::parse(&card_source);
+        }
+        if let Some(elapsed_source) = env_var("TIMESTAMP_ELAPSED_SOURCE") {
+            self.timestamp.elapsed_source = TimestampSource::parse(&elapsed_source);
+        }
+
+        if let Some(heartbeat_secs) =
+            env_var("CONNECTION_HEARTBEAT_SECS").and_then(|h| h.parse().ok())
+        {
+            self.connection.heartbeat_secs = heartbeat_secs;
+        }
+        if let Some(lazy) = env_var("CONNECTION_LAZY").and_then(|l| parse_bool(&l)) {
+            self.connection.lazy = lazy;
+        }
+
+        env_option!(self.events.socket_path, "EVENTS_SOCKET_PATH");
+
+        if let Some(git_integration) = env_var("GIT_INTEGRATION").and_then(|g| parse_bool(&g)) {
+            self.git_integration = git_integration;
+        }
+        if let Some(hide_private) = env_var("GIT_HIDE_PRIVATE").and_then(|h| parse_bool(&h)) {
+            self.git.hide_private = hide_private;
+        }
+        if let Some(public_hosts) = env_var("GIT_PUBLIC_HOSTS") {
+            self.git.public_hosts = comma_separated(&public_hosts);
+        }
+        if let Some(private_hosts) = env_var("GIT_PRIVATE_HOSTS") {
+            self.git.private_hosts = comma_separated(&private_hosts);
+        }
+        if let Some(button_label) = env_var("GIT_BUTTON_LABEL").filter(|b| !b.is_empty()) {
+            self.git.button_label = button_label;
+        }
+
+        if let Some(unknown_env_vars) = env_var("UNKNOWN_ENV_VARS") {
+            self.unknown_env_vars = UnknownEnvVars::parse(&unknown_env_vars);
+        }
+    }
+}
+
+/// Writes minutes since midnight back as `HH:MM`, like in the options.
+fn serialize_time<S: Serializer>(minutes: &u16, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:02}:{:02}", minutes / 60, minutes % 60))
+}
+
+/// Writes weekdays back as short names like `mon`.
+fn serialize_days<S: Serializer>(days: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(days.iter().map(|day| &WEEKDAYS[usize::from(*day)][..3]))
+}
+
+/// Writes pairs kept in their configured order as an object, like in the options.
+fn serialize_pairs<S: Serializer, T: Serialize>(
+    pairs: &[(String, T)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(pairs.iter().map(|(key, value)| (key, value)))
+}
+
+/// Reads the TOML file pointed to by `DISCORD_PRESENCE_CONFIG`, a malformed file is ignored.
+fn config_file() -> Option<Value> {
+    let path = env_var("CONFIG")
+        .filter(|path| !path.is_empty())
+        .map(|path| expand_path(&path))?;
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("Failed to read config file {path}: {e}");
+            return None;
+        }
+    };
+
+    match toml::from_str::<toml::Value>(&contents).map(serde_json::to_value) {
+        Ok(Ok(options)) => Some(options),
+        Ok(Err(e)) => {
+            warn!("Failed to convert config file {path}: {e}");
+            None
+        }
+        Err(e) => {
+            warn!("Ignoring malformed config file {path}: {e}");
+            None
+        }
+    }
+}
+
+/// Expands `$VAR` and `${VAR}` in every string value, including nested ones and profiles.
+fn expand_env_values(options: &mut Value, keep_unknown: bool) {
+    match options {
+        Value::String(value) => *value = expand_env(value, keep_unknown),
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| expand_env_values(value, keep_unknown)),
+        Value::Object(values) => values
+            .values_mut()
+            .for_each(|value| expand_env_values(value, keep_unknown)),
+        _ => {}
+    }
+}
+
+/// Options may arrive wrapped in a `settings` and/or `discord_presence` object,
+/// e.g. from `workspace/didChangeConfiguration`.
+fn unwrap_envelope(mut options: Value) -> Value {
+    for key in ["settings", "discord_presence"] {
+        if let Some(inner) = options.get_mut(key).filter(|inner| inner.is_object()) {
+            options = inner.take();
+        }
+    }
+
+    options
+}
+
+/// Options must be an object, anything else is ignored with a warning as none of them would apply.
+fn object_options(options: Value) -> Option<Value> {
+    match options {
+        Value::Object(_) => Some(options),
+        Value::Null => None,
+        _ => {
+            warn!("Ignoring options as they aren't an object, using the defaults: {options}");
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    OptionalString, // null resets the field
+    Bool,
+    Number,
+    Duration, // seconds or a duration string, invalid strings fall back to the default
+    StringArray,
+    Object,
+}
+
+impl Kind {
+    fn expected(self) -> &'static str {
+        match self {
+            Kind::String => "a string",
+            Kind::OptionalString => "a string or null",
+            Kind::Bool => "a boolean",
+            Kind::Number => "a whole number",
+            Kind::Duration => "a whole number or a duration like \"15m\"",
+            Kind::StringArray => "an array of strings",
+            Kind::Object => "an object",
+        }
+    }
+
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Kind::String => value.is_string(),
+            // Anything else falls back to its default, like an absent field
+            _ if value.is_null() => true,
+            Kind::OptionalString => value.is_string(),
+            Kind::Bool => value.is_boolean(),
+            Kind::Number => value.is_u64(),
+            Kind::Duration => value.is_u64() || value.is_string(),
+            Kind::StringArray => value
+                .as_array()
+                .is_some_and(|values| values.iter().all(Value::is_string)),
+            Kind::Object => value.is_object(),
+        }
+    }
+}
+
+const ACTIVITY_FIELDS: [&str; 6] = [
+    "state",
+    "details",
+    "large_image",
+    "large_text",
+    "small_image",
+    "small_text",
+];
+
+/// Fields besides the activity ones, a parent comes before its children
+const FIELDS: &[(&str, Kind)] = &[
+    ("application_id", Kind::String),
+    ("base_icons_url", Kind::String),
+    ("default_language", Kind::String),
+    ("git_state", Kind::OptionalString),
+    ("git_details", Kind::OptionalString),
+    ("no_git_state", Kind::OptionalString),
+    ("no_git_details", Kind::OptionalString),
+    ("fallback_image", Kind::OptionalString),
+    ("show_large_image", Kind::Bool),
+    ("show_small_image", Kind::Bool),
+    ("swap_images", Kind::Bool),
+    ("use_asset_keys", Kind::Bool),
+    ("status_small_image", Kind::Bool),
+    ("dirty_small_image", Kind::OptionalString),
+    ("hide_file_extension", Kind::Bool),
+    ("max_filename_length", Kind::Number),
+    ("only_workspace_files", Kind::Bool),
+    ("resolve_symlinks", Kind::Bool),
+    ("min_update_interval_secs", Kind::Number),
+    ("update_on", Kind::OptionalString),
+    ("startup_grace_ms", Kind::Number),
+    ("git_integration", Kind::Bool),
+    ("rules", Kind::Object),
+    ("rules.mode", Kind::OptionalString),
+    ("rules.paths", Kind::StringArray),
+    ("rules.remotes", Kind::StringArray),
+    ("rules.languages", Kind::StringArray),
+    ("rules.respect_gitignore", Kind::Bool),
+    ("idle", Kind::Object),
+    ("idle.enabled", Kind::Bool),
+    ("idle.timeout", Kind::Duration),
+    ("idle.action", Kind::OptionalString),
+    ("idle.keep_language", Kind::Bool),
+    ("idle.on_screen_lock", Kind::Bool),
+    ("idle.on_focus_loss", Kind::Bool),
+    ("idle.application_id", Kind::OptionalString),
+    ("idle.active_languages", Kind::StringArray),
+    ("idle.rotate", Kind::OptionalString),
+    ("collab", Kind::Object),
+    ("schedule", Kind::Object),
+    ("schedule.weekend", Kind::Object),
+    ("dnd", Kind::Object),
+    ("workspace_name", Kind::Object),
+    ("workspace_name.from_git", Kind::Bool),
+    ("party", Kind::Object),
+    ("party.size", Kind::Number),
+    ("party.max", Kind::Number),
+    ("privacy", Kind::Object),
+    ("privacy.mode", Kind::OptionalString),
+    ("timestamp", Kind::Object),
+    ("timestamp.mode", Kind::OptionalString),
+    ("timestamp.end_in_secs", Kind::Number),
+    ("timestamp.persist", Kind::Bool),
+    ("timestamp.grace_secs", Kind::Number),
+    ("timestamp.card_source", Kind::OptionalString),
+    ("timestamp.elapsed_source", Kind::OptionalString),
+    ("connection", Kind::Object),
+    ("connection.heartbeat_secs", Kind::Number),
+    ("connection.lazy", Kind::Bool),
+    ("events", Kind::Object),
+    ("events.socket_path", Kind::OptionalString),
+    ("git", Kind::Object),
+    ("git.hide_private", Kind::Bool),
+    ("git.public_hosts", Kind::StringArray),
+    ("git.private_hosts", Kind::StringArray),
+    ("git.button_label", Kind::OptionalString),
+    ("unknown_env_vars", Kind::OptionalString),
+    ("extensions", Kind::Object),
+    ("language_by_path", Kind::Object),
+    ("profiles", Kind::Object),
+];
+
+/// Checks the type of every known field, `prefix` names the object in errors.
+fn validate(options: &Value, prefix: &str) -> Result<(), ConfigError> {
+    let check = |path: &str, kind: Kind| match lookup(options, path) {
+        Some(value) if !kind.matches(value) => Err(ConfigError {
+            field: format!("{prefix}{path}"),
+            expected: kind.expected(),
+        }),
+        _ => Ok(()),
+    };
+
+    for (path, kind) in FIELDS {
+        check(path, *kind)?;
+    }
+
+    for parent in ["", "idle.", "collab.", "schedule.weekend."] {
+        for field in ACTIVITY_FIELDS {
+            check(&format!("{parent}{field}"), Kind::OptionalString)?;
+        }
+    }
+
+    if let Some(hours) = lookup(options, "schedule.hours").filter(|h| !h.is_null()) {
+        let hours = hours.as_array().ok_or_else(|| ConfigError {
+            field: format!("{prefix}schedule.hours"),
+            expected: "an array of objects",
+        })?;
+
+        for (i, entry) in hours.iter().enumerate() {
+            let entry_prefix = format!("{prefix}schedule.hours[{i}].");
+            if !entry.is_object() {
+                return Err(ConfigError {
+                    field: entry_prefix.trim_end_matches('.').to_string(),
+                    expected: Kind::Object.expected(),
+                });
+            }
+
+            for field in ["from", "to"].into_iter().chain(ACTIVITY_FIELDS) {
+                let value = &entry[field];
+                if !Kind::OptionalString.matches(value) {
+                    return Err(ConfigError {
+                        field: format!("{entry_prefix}{field}"),
+                        expected: Kind::OptionalString.expected(),
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(variants) = lookup(options, "idle.variants").filter(|v| !v.is_null()) {
+        let variants = variants.as_array().ok_or_else(|| ConfigError {
+            field: format!("{prefix}idle.variants"),
+            expected: "an array of objects",
+        })?;
+
+        for (i, variant) in variants.iter().enumerate() {
+            let variant_prefix = format!("{prefix}idle.variants[{i}]");
+            if !variant.is_object() {
+                return Err(ConfigError {
+                    field: variant_prefix,
+                    expected: Kind::Object.expected(),
+                });
+            }
+
+            for field in ACTIVITY_FIELDS {
+                if !Kind::OptionalString.matches(&variant[field]) {
+                    return Err(ConfigError {
+                        field: format!("{variant_prefix}.{field}"),
+                        expected: Kind::OptionalString.expected(),
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(hours) = lookup(options, "dnd.hours").filter(|h| !h.is_null()) {
+        let hours = hours.as_array().ok_or_else(|| ConfigError {
+            field: format!("{prefix}dnd.hours"),
+            expected: "an array of objects",
+        })?;
+
+        for (i, entry) in hours.iter().enumerate() {
+            let entry_prefix = format!("{prefix}dnd.hours[{i}].");
+            if !entry.is_object() {
+                return Err(ConfigError {
+                    field: entry_prefix.trim_end_matches('.').to_string(),
+                    expected: Kind::Object.expected(),
+                });
+            }
+
+            for (field, kind) in [
+                ("from", Kind::OptionalString),
+                ("to", Kind::OptionalString),
+                ("days", Kind::StringArray),
+            ] {
+                if !kind.matches(&entry[field]) {
+                    return Err(ConfigError {
+                        field: format!("{entry_prefix}{field}"),
+                        expected: kind.expected(),
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(extensions) = options.get("extensions").and_then(Value::as_object) {
+        for (extension, activity) in extensions {
+            let extension_prefix = format!("{prefix}extensions.{extension}");
+            if !activity.is_object() {
+                return Err(ConfigError {
+                    field: extension_prefix,
+                    expected: Kind::Object.expected(),
+                });
+            }
+
+            for field in ACTIVITY_FIELDS {
+                if !Kind::OptionalString.matches(&activity[field]) {
+                    return Err(ConfigError {
+                        field: format!("{extension_prefix}.{field}"),
+                        expected: Kind::OptionalString.expected(),
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(language_by_path) = options.get("language_by_path").and_then(Value::as_object) {
+        for (pattern, language) in language_by_path {
+            if !language.is_string() {
+                return Err(ConfigError {
+                    field: format!("{prefix}language_by_path.{pattern}"),
+                    expected: Kind::String.expected(),
+                });
+            }
+        }
+    }
+
+    if let Some(profiles) = options.get("profiles").and_then(Value::as_object) {
+        for (pattern, overlay) in profiles {
+            let profile_prefix = format!("{prefix}profiles.{pattern}");
+            if !overlay.is_object() {
+                return Err(ConfigError {
+                    field: profile_prefix,
+                    expected: Kind::Object.expected(),
+                });
+            }
+            validate(overlay, &format!("{profile_prefix}."))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Follows a dotted path like `idle.timeout` through nested objects.
+fn lookup<'a>(options: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(options, |value, key| value.as_object()?.get(key))
+}
+
+fn string_array(value: &Value) -> Vec<String> {
+    value.as_array().map_or(Vec::new(), |values| {
+        values
+            .iter()
+            .filter_map(|v| v.as_str().map(|s| s.to_string()))
+            .collect()
+    })
+}
+
+fn comma_separated(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn env_var(key: &str) -> Option<String> {
+    env::var(format!("{ENV_PREFIX}{key}")).ok()
+}
+
+/// Environment variables are process-wide, tests setting them or reading the configuration
+/// from them must not run concurrently. Backend tests hold it across `initialize`.
+#[cfg(test)]
+pub static ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::json;
+    use tower_lsp::lsp_types::Url;
+
+    use super::*;
+    use crate::{git::GitStatus, util::Placeholders, Document};
+
+    fn with_env<F: FnOnce()>(vars: &[(&str, &str)], f: F) {
+        let _guard = ENV_LOCK.blocking_lock();
+
+        for (key, value) in vars {
+            env::set_var(format!("{ENV_PREFIX}{key}"), value);
+        }
+
+        f();
+
+        for (key, _) in vars {
+            env::remove_var(format!("{ENV_PREFIX}{key}"));
+        }
+    }
+
+    #[test]
+    fn test_env_overrides_initialization_options() {
+        with_env(
+            &[
+                ("APPLICATION_ID", "1234"),
+                ("STATE", "Hacking"),
+                ("DETAILS", ""),
+                ("IDLE_TIMEOUT", "60"),
+                ("IDLE_ACTION", "clear_activity"),
+                ("GIT_INTEGRATION", "no"),
+            ],
+            || {
+                let mut config = Configuration::new();
+                config
+                    .set(Some(json!({
+                        "application_id": "5678",
+                        "state": "Working on {filename}",
+                        "git_integration": true,
+                    })))
+                    .unwrap();
+
+                assert_eq!(config.application_id, "1234");
+                assert_eq!(config.state.as_deref(), Some("Hacking"));
+                assert_eq!(config.details, None);
+                assert_eq!(config.idle.timeout, 60);
+                assert_eq!(config.idle.action, IdleAction::ClearActivity);
+                assert!(!config.git_integration);
+            },
+        );
+    }
+
+    #[test]
+    fn test_invalid_env_values_are_ignored() {
+        with_env(
+            &[("IDLE_TIMEOUT", "soon"), ("GIT_INTEGRATION", "maybe")],
+            || {
+                let mut config = Configuration::new();
+                config.set(None).unwrap();
+
+                assert_eq!(config.idle.timeout, 300);
+                assert!(config.git_integration);
+            },
+        );
+    }
+
+    #[test]
+    fn test_flat_and_wrapped_options() {
+        let _guard = ENV_LOCK.blocking_lock();
+
+        for options in [
+            json!({ "state": "Flat" }),
+            json!({ "discord_presence": { "state": "Flat" } }),
+            json!({ "settings": { "discord_presence": { "state": "Flat" } } }),
+            json!({ "settings": { "state": "Flat" } }),
+        ] {
+            let mut config = Configuration::new();
+            config.set(Some(options)).unwrap();
+
+            assert_eq!(config.state.as_deref(), Some("Flat"));
+        }
+    }
+
+    #[test]
+    fn test_non_object_options_are_ignored() {
+        let _guard = ENV_LOCK.blocking_lock();
+
+        let options = json!(["state", "Working"]);
+        assert_eq!(object_options(options.clone()), None);
+        assert_eq!(object_options(Value::Null), None);
+
+        let mut config = Configuration::new();
+        config.set(Some(options)).unwrap();
+
+        let defaults = Configuration::new();
+        assert_eq!(config.state, defaults.state);
+        assert_eq!(config.application_id, defaults.application_id);
+        assert_eq!(config.idle.timeout, defaults.idle.timeout);
+    }
+
+    #[test]
+    fn test_idle_options_do_not_override_activity() {
+        let _guard = ENV_LOCK.blocking_lock();
+
+        let mut config = Configuration::new();
+        config
+            .set(Some(json!({
+                "state": "Working",
+                "idle": { "state": "Away" },
+                "collab": { "state": "Pairing" },
+            })))
+            .unwrap();
+
+        assert_eq!(config.state.as_deref(), Some("Working"));
+        assert_eq!(config.idle.state.as_deref(), Some("Away"));
+        assert_eq!(config.collab.state.as_deref(), Some("Pairing"));
+    }
+
+    #[test]
+    fn test_git_private_hosts() {
+        let mut git = Git {
+            hide_private: true,
+            ..Default::default()
+        };
+        git.private_hosts.push(String::from("*.corp.example.com"));
+        git.public_hosts.push(String::from("*.example.com"));
+
+        assert!(!git.hides("https://github.com/xhyrom/zed-discord-presence"));
+        assert!(!git.hides("https://GitLab.com/group/repo"));
+        assert!(!git.hides("https://git.example.com/repo"));
+        assert!(git.hides("https://git.corp.example.com/repo"));
+        assert!(git.hides("https://selfhosted.dev/repo"));
+        assert!(git.hides("https://notexample.com/repo"));
+
+        git.hide_private = false;
+        assert!(!git.hides("https://selfhosted.dev/repo"));
+    }
+
+    #[test]
+    fn test_rules_remotes() {
+        let mut rules = Rules {
+            remotes: vec![
+                String::from("*.corp.example.com"),
+                String::from("github.com/xhyrom"),
+            ],
+            ..Default::default()
+        };
+        let work = Some("git@gitlab.corp.example.com:team/repo.git");
+        let own = Some("https://github.com/xhyrom/zed-discord-presence");
+        let other = Some("https://github.com/zed-industries/zed");
+
+        assert!(!rules.suitable("/work", work));
+        assert!(!rules.suitable("/own", own));
+        assert!(rules.suitable("/other", other));
+        assert!(rules.suitable("/none", None));
+
+        rules.mode = RulesMode::Whitelist;
+        assert!(rules.suitable("/work", work));
+        assert!(rules.suitable("/own", own));
+        assert!(!rules.suitable("/other", other));
+        assert!(!rules.suitable("/none", None));
+
+        rules.paths.push(String::from("/none"));
+        assert!(rules.suitable("/none", None));
+    }
+
+    #[test]
+    fn test_rules_relative_paths() {
+        let mut rules = Rules {
+            paths: vec![String::from("secrets/**"), String::from("*.env")],
+            ..Default::default()
+        };
+
+        // The same files in workspaces at different locations
+        for workspace in ["/home/user/project", "/mnt/c/checkouts/project-copy"] {
+            let document = |file: &str| format!("{workspace}/{file}");
+            let relative = |file: &str| {
+                let path = document(file);
+                Path::new(&path)
+                    .strip_prefix(workspace)
+                    .ok()
+                    .map(|p| p.to_string_lossy().into_owned())
+            };
+
+            assert!(rules.suitable(workspace, None));
+            assert!(!rules.allows_document(relative("secrets/keys/id.pem").as_deref()));
+            assert!(!rules.allows_document(relative("config/prod.env").as_deref()));
+            assert!(!rules.allows_document(relative(".env").as_deref()));
+            assert!(rules.allows_document(relative("src/secrets.rs").as_deref()));
+        }
+        assert!(rules.allows_document(None));
+
+        rules.mode = RulesMode::Whitelist;
+        assert!(rules.suitable("/home/user/project", None));
+        assert!(rules.allows_document(Some("secrets/keys/id.pem")));
+        assert!(!rules.allows_document(Some("src/main.rs")));
+        assert!(!rules.allows_document(None));
+
+        rules.paths.push(String::from("/home/user/*"));
+        assert!(rules.suitable("/home/user/project", None));
+        assert!(!rules.suitable("/mnt/c/checkouts/project-copy", None));
+    }
+
+    #[test]
+    fn test_update_on_accepts() {
+        assert!(UpdateOn::Change.accepts(UpdateOn::Change));
+        assert!(UpdateOn::Change.accepts(UpdateOn::Open));
+        assert!(!UpdateOn::Save.accepts(UpdateOn::Change));
+        assert!(UpdateOn::Save.accepts(UpdateOn::Save));
+        assert!(UpdateOn::Save.accepts(UpdateOn::Open));
+        assert!(!UpdateOn::Open.accepts(UpdateOn::Save));
+    }
+
+    #[test]
+    fn test_rules_languages() {
+        let mut rules = Rules::default();
+        assert!(rules.allows_language("markdown"));
+
+        rules.languages.push(String::from("Markdown"));
+        assert!(!rules.allows_language("markdown"));
+        assert!(rules.allows_language("rust"));
+
+        rules.mode = RulesMode::Whitelist;
+        assert!(rules.allows_language("markdown"));
+        assert!(!rules.allows_language("rust"));
+    }
+
+    #[test]
+    fn test_base_icons_url_normalization() {
+        let _guard = ENV_LOCK.blocking_lock();
+
+        for (url, expected) in [
+            ("https://example.com/icons/", "https://example.com/icons"),
+            ("https://example.com/icons", "https://example.com/icons"),
+            ("http://localhost:8080/", "http://localhost:8080"),
+            ("file:///home/user/icons/", "file:///home/user/icons"),
+            ("raw.githubusercontent.com/icons/", DEFAULT_BASE_ICONS_URL),
+        ] {
+            let mut config = Configuration::new();
+            config.set(Some(json!({ "base_icons_url": url }))).unwrap();
+
+            assert_eq!(config.base_icons_url, expected);
+        }
+    }
+
+    #[test]
+    fn test_local_base_icons_url() {
+        let _guard = ENV_LOCK.blocking_lock();
+
+        let git = GitStatus::default();
+        let document = Document::new(Url::parse("file:///home/user/main.rs").unwrap());
+
+        for (url, expected) in [
+            (
+                "file:///home/user/icons/",
+                "file:///home/user/icons/rust.png",
+            ),
+            ("http://localhost:8080", "http://localhost:8080/rust.png"),
+        ] {
+            let mut config = Configuration::new();
+            config.set(Some(json!({ "base_icons_url": url }))).unwrap();
+
+            let placeholders = Placeholders::new(Some(&document), &config, "workspace", &git);
+            assert_eq!(
+                placeholders.replace("{base_icons_url}/{language}.png"),
+                expected
+            );
+        }
+    }
+
+    fn config_path(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("discord-presence-lsp-test-{name}.toml"));
+        fs::write(&path, contents).unwrap();
+
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_config_file_overrides_initialization_options() {
+        let path = config_path(
+            "config",
+            r#"
+            state = "From TOML"
+
+            [idle]
+            timeout = 60
+            "#,
+        );
+
+        with_env(&[("CONFIG", &path)], || {
+            let mut config = Configuration::new();
+            config
+                .set(Some(json!({
+                    "state": "From JSON",
+                    "details": "In {workspace:u}",
+                })))
+                .unwrap();
+
+            assert_eq!(config.state.as_deref(), Some("From TOML"));
+            assert_eq!(config.details.as_deref(), Some("In {workspace:u}"));
+            assert_eq!(config.idle.timeout, 60);
+        });
+    }
+
+    #[test]
+    fn test_malformed_config_file_is_ignored() {
+        let path = config_path("malformed", "state = ");
+
+        with_env(&[("CONFIG", &path)], || {
+            let mut config = Configuration::new();
+            config.set(Some(json!({ "state": "From JSON" }))).unwrap();
+
+            assert_eq!(config.state.as_deref(), Some("From JSON"));
+        });
+    }
+
+    #[test]
+    fn test_profiles() {
+        let _guard = ENV_LOCK.blocking_lock();
+
+        let options = json!({
+            "details": "In {workspace}",
+            "git_integration": true,
+            "profiles": {
+                "/home/user/clients/**": {
+                    "details": "Working for a client",
+                    "git_integration": false,
+                },
+                "/home/user/**": { "details": "Hacking on {workspace}" },
+            },
+        });
+
+        let mut client = Configuration::new();
+        client.set_workspace_path("/home/user/clients/acme");
+        client.set(Some(options.clone())).unwrap();
+        assert_eq!(client.details.as_deref(), Some("Working for a client"));
+        assert!(!client.git_integration);
+
+        let mut oss = Configuration::new();
+        oss.set_workspace_path("/home/user/oss/zed");
+        oss.set(Some(options.clone())).unwrap();
+        assert_eq!(oss.details.as_deref(), Some("Hacking on {workspace}"));
+        assert!(oss.git_integration);
+
+        let mut other = Configuration::new();
+        other.set_workspace_path("/srv/project");
+        other.set(Some(options)).unwrap();
+        assert_eq!(other.details.as_deref(), Some("In {workspace}"));
+        assert!(other.git_integration);
+    }
+
+    #[test]
+    fn test_schedule() {
+        let _guard = ENV_LOCK.blocking_lock();
+
+        let mut config = Configuration::new();
+        config
+            .set(Some(json!({
+                "schedule": {
+                    "weekend": { "state": "Coding on a weekend 😎" },
+                    "hours": [
+                        { "from": "22:00", "to": "06:00", "details": "Burning the midnight oil" },
+                        { "from": "25:00", "to": "26:00", "details": "Never" },
+                    ],
+                },
+            })))
+            .unwrap();
+        assert_eq!(config.schedule.hours.len(), 1);
+
+        let saturday_noon = LocalTime {
+            weekday: 6,
+            minutes: 12 * 60,
+        };
+        let active = config.schedule.active(saturday_noon).unwrap();
+        assert_eq!(active.state.as_deref(), Some("Coding on a weekend 😎"));
+
+        let wednesday_noon = LocalTime {
+            weekday: 3,
+            minutes: 12 * 60,
+        };
+        assert!(config.schedule.active(wednesday_noon).is_none());
+
+        for minutes in [23 * 60, 5 * 60 + 59] {
+            let night = LocalTime {
+                weekday: 3,
+                minutes,
+            };
+            let active = config.schedule.active(night).unwrap();
+            assert_eq!(active.details.as_deref(), Some("Burning the midnight oil"));
+        }
+
+        let morning = LocalTime {
+            weekday: 3,
+            minutes: 6 * 60,
+        };
+        assert!(config.schedule.active(morning).is_none());
+    }
+
+    #[test]
+    fn test_dnd() {
+        let _guard = ENV_LOCK.blocking_lock();
+
+        let mut config = Configuration::new();
+        config
+            .set(Some(json!({
+                "dnd": {
+                    "hours": [
+                        { "from": "18:00", "to": "09:00", "days": ["mon", "Tuesday"] },
+                        { "from": "12:00", "to": "13:00" },
+                        { "from": "08:00", "to": "10:00", "days": ["someday"] },
+                    ],
+                },
+            })))
+            .unwrap();
+        assert_eq!(config.dnd.hours.len(), 2);
+
+        let at = |weekday, hours: u16| LocalTime {
+            weekday,
+            minutes: hours * 60,
+        };
+
+        // Monday evening until Wednesday morning
+        assert!(!config.dnd.active(at(1, 17)));
+        assert!(config.dnd.active(at(1, 18)));
+        assert!(config.dnd.active(at(2, 8)));
+        assert!(config.dnd.active(at(3, 8)));
+        assert!(!config.dnd.active(at(3, 9)));
+        assert!(!config.dnd.active(at(3, 20)));
+        assert!(!config.dnd.active(at(1, 8)));
+
+        // Every day without `days`
+        assert!(config.dnd.active(at(0, 12)));
+        assert!(!config.dnd.active(at(0, 13)));
+
+        let serialized = serde_json::to_value(&config.dnd).unwrap();
+        assert_eq!(serialized["hours"][0]["days"], json!(["mon", "tue"]));
+    }
+
+    #[test]
+    fn test_idle_active_languages() {
+        let mut idle = Idle::default();
+        assert!(idle.counts("markdown", "md"));
+
+        idle.active_languages = vec![String::from("Rust"), String::from(".toml")];
+        assert!(idle.counts("rust", "rs"));
+        assert!(idle.counts("toml", "toml"));
+        assert!(!idle.counts("markdown", "md"));
+        assert!(!idle.counts("markdown", "toml.md"));
+    }
+
+    #[test]
+    fn test_idle_timeout_durations() {
+        with_env(&[], || {
+            let timeout = |value: Value| {
+                let mut config = Configuration::new();
+                config
+                    .set(Some(json!({ "idle": { "timeout": value } })))
+                    .unwrap();
+                config.idle.timeout
+            };
+
+            assert_eq!(timeout(json!(600)), 600);
+            assert_eq!(timeout(json!("10m")), 600);
+            assert_eq!(timeout(json!("1h")), 3600);
+            assert_eq!(timeout(json!("1h30m")), 5400);
+            assert_eq!(timeout(json!("soon")), 300);
+            assert_eq!(timeout(json!("1h30")), 300);
+        });
+    }
+
+    #[test]
+    fn test_party_size() {
+        let mut party = Party::default();
+        assert_eq!(party.size(), None);
+
+        party.size = Some(1);
+        assert_eq!(party.size(), None);
+
+        party.max = Some(3);
+        assert_eq!(party.size(), Some([1, 3]));
+
+        party.size = Some(4);
+        assert_eq!(party.size(), None);
+    }
+
+    #[test]
+    fn test_env_expansion_in_values() {
+        with_env(&[("TEST_HOST", "https://icons.example.com")], || {
+            let mut config = Configuration::new();
+            config
+                .set(Some(json!({
+                    "base_icons_url": "$DISCORD_PRESENCE_TEST_HOST/icons/",
+                    "state": "Costs $$5 on ${DISCORD_PRESENCE_TEST_HOST}",
+                    "details": "In $DISCORD_PRESENCE_UNSET_VARIABLE",
+                    "idle": { "large_image": "${DISCORD_PRESENCE_TEST_HOST}/idle.png" },
+                    "rules": { "paths": ["~/$DISCORD_PRESENCE_UNSET_VARIABLE"] }
+                })))
+                .unwrap();
+
+            let home = env::var("HOME").unwrap();
+            assert_eq!(config.base_icons_url, "https://icons.example.com/icons");
+            assert_eq!(
+                config.state.as_deref(),
+                Some("Costs $5 on https://icons.example.com")
+            );
+            assert_eq!(
+                config.details.as_deref(),
+                Some("In $DISCORD_PRESENCE_UNSET_VARIABLE")
+            );
+            assert_eq!(
+                config.idle.large_image.as_deref(),
+                Some("https://icons.example.com/idle.png")
+            );
+            assert_eq!(
+                config.rules.paths,
+                vec![format!("{home}/$DISCORD_PRESENCE_UNSET_VARIABLE")]
+            );
+
+            let mut config = Configuration::new();
+            config
+                .set(Some(json!({
+                    "unknown_env_vars": "empty",
+                    "details": "In $DISCORD_PRESENCE_UNSET_VARIABLE",
+                    "state": "$$DISCORD_PRESENCE_UNSET_VARIABLE"
+                })))
+                .unwrap();
+
+            assert_eq!(config.details.as_deref(), Some("In "));
+            assert_eq!(
+                config.state.as_deref(),
+                Some("$DISCORD_PRESENCE_UNSET_VARIABLE")
+            );
+        });
+    }
+
+    #[test]
+    fn test_malformed_fields() {
+        with_env(&[], || {
+            let error = |options: Value| {
+                Configuration::new()
+                    .set(Some(options))
+                    .unwrap_err()
+                    .to_string()
+            };
+
+            assert_eq!(
+                error(json!({ "idle": { "timeout": true } })),
+                "idle.timeout must be a whole number or a duration like \"15m\""
+            );
+            assert_eq!(
+                error(json!({ "application_id": 1234 })),
+                "application_id must be a string"
+            );
+            assert_eq!(
+                error(json!({ "idle": { "variants": [{ "state": "Away" }, { "state": 1 }] } })),
+                "idle.variants[1].state must be a string or null"
+            );
+            assert_eq!(
+                error(json!({ "rules": { "paths": ["~/work", 1] } })),
+                "rules.paths must be an array of strings"
+            );
+            assert_eq!(
+                error(json!({ "collab": { "state": true } })),
+                "collab.state must be a string or null"
+            );
+            assert_eq!(
+                error(json!({ "schedule": { "hours": [{ "from": 9, "to": "17:00" }] } })),
+                "schedule.hours[0].from must be a string or null"
+            );
+            assert_eq!(
+                error(json!({ "profiles": { "~/work/**": { "git": "no" } } })),
+                "profiles.~/work/**.git must be an object"
+            );
+        });
+    }
+
+    #[test]
+    fn test_malformed_fields_keep_configuration() {
+        with_env(&[], || {
+            let mut config = Configuration::new();
+            config
+                .set(Some(
+                    json!({ "state": "Working", "idle": { "timeout": 60 } }),
+                ))
+                .unwrap();
+
+            assert!(config
+                .set(Some(json!({ "state": "Other", "swap_images": "yes" })))
+                .is_err());
+            assert_eq!(config.state.as_deref(), Some("Working"));
+
+            // Null falls back to the default like an absent field
+            config
+                .set(Some(
+                    json!({ "idle": { "timeout": null }, "details": null }),
+                ))
+                .unwrap();
+            assert_eq!(config.idle.timeout, 300);
+            assert_eq!(config.details, None);
+        });
+    }
+}
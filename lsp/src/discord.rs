@@ -17,20 +17,146 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>
  */
 
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
 use tokio::sync::{Mutex, MutexGuard};
+use tokio::time;
+use tracing::{debug, warn};
 
 use discord_rich_presence::{
-    activity::{Activity, Assets, Button, Timestamps},
+    activity::{Activity, Assets, Button, Party, Timestamps},
     DiscordIpc, DiscordIpcClient,
 };
 
 use crate::util;
 
+type IpcClient = Box<dyn DiscordIpc + Send>;
+
+/// How long to wait for the client before giving up on an update
+const CLIENT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
+pub struct LockTimeout(Duration);
+
+impl fmt::Display for LockTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {:?} waiting for the Discord client",
+            self.0
+        )
+    }
+}
+
+impl Error for LockTimeout {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionStatus {
+    Disconnected,
+    Connected,
+    Reconnecting,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CurrentActivity {
+    pub activity: Option<ActivityFields>,
+    pub status: ConnectionStatus,
+}
+
+/// Counters for bug reports, only read on demand through `discord-presence/stats`.
+#[derive(Debug, Default)]
+struct Counters {
+    updates_sent: AtomicU64,
+    reconnects: AtomicU64,
+    last_error: StdMutex<Option<String>>,
+    connected_since: StdMutex<Option<Instant>>,
+}
+
+impl Counters {
+    fn error(&self, error: impl fmt::Display) {
+        *self.last_error.lock().unwrap() = Some(error.to_string());
+    }
+
+    fn status(&self, status: ConnectionStatus) {
+        let mut connected_since = self.connected_since.lock().unwrap();
+        match status {
+            ConnectionStatus::Connected => {
+                connected_since.get_or_insert_with(Instant::now);
+            }
+            _ => *connected_since = None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub updates_sent: u64,
+    pub reconnects: u64,
+    pub last_error: Option<String>,
+    pub uptime_secs: Option<u64>, // since the connection was last established
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ActivityFields {
+    pub state: Option<String>,
+    pub details: Option<String>,
+    pub large_image: Option<String>,
+    pub large_text: Option<String>,
+    pub small_image: Option<String>,
+    pub small_text: Option<String>,
+    pub git_remote_url: Option<String>,
+    pub git_button_label: Option<String>,
+    pub party_size: Option<[u32; 2]>, // current and maximum
+    #[serde(skip)]
+    pub application_id: Option<String>, // shown as another application, e.g. while idle
+    #[serde(skip)]
+    pub start: Option<Duration>, // replaces the start of the session, e.g. from `timestamp.card_source`
+}
+
+impl ActivityFields {
+    pub fn is_empty(&self) -> bool {
+        [
+            &self.state,
+            &self.details,
+            &self.large_image,
+            &self.large_text,
+            &self.small_image,
+            &self.small_text,
+            &self.git_remote_url,
+        ]
+        .iter()
+        .all(|field| field.is_none())
+    }
+}
+
 pub struct Discord {
-    client: Option<Mutex<DiscordIpcClient>>,
+    client: Option<Mutex<IpcClient>>,
+    new_client: Box<dyn Fn(&str) -> IpcClient + Send + Sync>,
+    application_id: String,
+    active_application_id: Mutex<String>, // the one the client is connected as
     start_timestamp: Duration,
+    end_timestamp: Option<Duration>,
+    last_activity: Mutex<Option<ActivityFields>>,
+    status: Mutex<ConnectionStatus>,
+    counters: Counters,
+    suspended: AtomicBool, // keeps track of the activity without sending it
+    lock_timeout: Duration,
+}
+
+impl Debug for Discord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Discord")
+            .field("client_initialized", &self.client.is_some())
+            .field("start_timestamp", &self.start_timestamp)
+            .field("end_timestamp", &self.end_timestamp)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Discord {
@@ -42,80 +168,726 @@ impl Discord {
 
         Self {
             client: None,
+            new_client: Box::new(|application_id| {
+                Box::new(
+                    DiscordIpcClient::new(application_id)
+                        .expect("Failed to initialize Discord Ipc Client"),
+                )
+            }),
+            application_id: String::new(),
+            active_application_id: Mutex::new(String::new()),
             start_timestamp: since_epoch,
+            end_timestamp: None,
+            last_activity: Mutex::new(None),
+            status: Mutex::new(ConnectionStatus::Disconnected),
+            counters: Counters::default(),
+            suspended: AtomicBool::new(false),
+            lock_timeout: CLIENT_LOCK_TIMEOUT,
         }
     }
 
     pub fn create_client(&mut self, application_id: String) {
-        let discord_client = DiscordIpcClient::new(application_id.as_str())
-            .expect("Failed to initialize Discord Ipc Client");
+        self.client = Some(Mutex::new((self.new_client)(&application_id)));
+        self.active_application_id = Mutex::new(application_id.clone());
+        self.application_id = application_id;
+    }
 
-        self.client = Some(Mutex::new(discord_client));
+    pub fn start_timestamp(&self) -> Duration {
+        self.start_timestamp
     }
 
-    pub async fn connect(&self) {
-        let mut client = self.get_client().await;
-        let result = client.connect();
-        result.unwrap();
+    /// Continues a previous session, set before `set_end_in` as the end is relative to the start.
+    pub fn set_start(&mut self, start: Duration) {
+        self.start_timestamp = start;
     }
 
+    /// Shows a countdown ending `end_in_secs` after the start instead of the elapsed time.
+    pub fn set_end_in(&mut self, end_in_secs: Option<u64>) {
+        self.end_timestamp =
+            end_in_secs.map(|secs| self.start_timestamp + Duration::from_secs(secs));
+    }
+
+    pub async fn connect(&self) -> Result<(), String> {
+        let mut client = self.get_client().await.map_err(|e| e.to_string())?;
+        client.connect().map_err(|e| {
+            self.counters.error(&e);
+            e.to_string()
+        })?;
+
+        self.set_status(&mut *self.status.lock().await, ConnectionStatus::Connected);
+        Ok(())
+    }
+
+    /// Clears the activity before closing the connection, otherwise Discord
+    /// may keep showing the last activity for a while after the socket closes.
     pub async fn kill(&self) {
-        let mut client = self.get_client().await;
-        let result = client.close();
-        result.unwrap();
+        let Some(mut client) = self.try_get_client().await else {
+            return;
+        };
+
+        let mut status = self.status.lock().await;
+        // There is no socket to close when it never connected, e.g. without Discord running
+        if *status == ConnectionStatus::Disconnected {
+            return;
+        }
+
+        if let Err(e) = client.clear_activity() {
+            debug!("Failed to clear activity before closing: {e}");
+        }
+
+        self.set_status(&mut status, ConnectionStatus::Disconnected);
+
+        if let Err(e) = client.close() {
+            debug!("Failed to close the Discord connection: {e}");
+        }
     }
 
-    pub async fn get_client(&self) -> MutexGuard<'_, DiscordIpcClient> {
-        self.client
+    /// Fails instead of waiting forever when an update got stuck holding the client.
+    pub async fn get_client(&self) -> Result<MutexGuard<'_, IpcClient>, LockTimeout> {
+        let client = self
+            .client
             .as_ref()
-            .expect("Discord client not initialized")
-            .lock()
+            .expect("Discord client not initialized");
+
+        time::timeout(self.lock_timeout, client.lock())
             .await
+            .map_err(|_| LockTimeout(self.lock_timeout))
+    }
+
+    /// Marks the connection as reconnecting on a timeout, so the next heartbeat
+    /// restores the last activity once the client is free again.
+    async fn try_get_client(&self) -> Option<MutexGuard<'_, IpcClient>> {
+        match self.get_client().await {
+            Ok(client) => Some(client),
+            Err(e) => {
+                warn!("{e}");
+                self.counters.error(&e);
+                self.set_status(
+                    &mut *self.status.lock().await,
+                    ConnectionStatus::Reconnecting,
+                );
+                None
+            }
+        }
     }
 
     pub async fn clear_activity(&self) {
-        let mut client = self.get_client().await;
-        client
-            .clear_activity()
-            .unwrap_or_else(|_| println!("Failed to clear activity"));
+        self.update(None).await;
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub async fn change_activity(
+    /// Clears the activity instead when every field is empty, as Discord doesn't render an empty one.
+    pub async fn change_activity(&self, fields: ActivityFields) {
+        if fields.is_empty() {
+            self.clear_activity().await;
+            return;
+        }
+
+        self.use_application(fields.application_id.as_deref()).await;
+        self.update(Some(fields)).await;
+    }
+
+    /// Reconnects as another application, `None` goes back to the one the client was created
+    /// with. Nothing happens when the client is connected as that application already.
+    async fn use_application(&self, application_id: Option<&str>) {
+        let application_id = application_id.unwrap_or(&self.application_id);
+        let mut active = self.active_application_id.lock().await;
+        if *active == application_id {
+            return;
+        }
+
+        let Some(mut client) = self.try_get_client().await else {
+            return;
+        };
+
+        // Otherwise the previous application keeps showing its activity for a while
+        if let Err(e) = client.clear_activity() {
+            debug!("Failed to clear activity before switching applications: {e}");
+        }
+        if let Err(e) = client.close() {
+            debug!("Failed to close the connection before switching applications: {e}");
+        }
+
+        *client = (self.new_client)(application_id);
+        *active = application_id.to_string();
+
+        let mut status = self.status.lock().await;
+        match client.connect() {
+            Ok(()) => self.set_status(&mut status, ConnectionStatus::Connected),
+            Err(e) => {
+                debug!("Failed to connect as application {application_id}: {e}");
+                self.counters.error(&e);
+                self.set_status(&mut status, ConnectionStatus::Disconnected);
+            }
+        }
+    }
+
+    /// Remembers the activity before sending it, so a reconnect restores the newest one
+    /// even when the client is unavailable right now.
+    async fn update(&self, fields: Option<ActivityFields>) {
+        if self.suspended.load(Ordering::Relaxed) {
+            *self.last_activity.lock().await = fields;
+            return;
+        }
+
+        let client = self.try_get_client().await;
+        let mut last_activity = self.last_activity.lock().await;
+        *last_activity = fields;
+
+        if let Some(mut client) = client {
+            let mut status = self.status.lock().await;
+            self.send_or_reconnect(&mut client, last_activity.as_ref(), &mut status);
+        }
+    }
+
+    /// Re-sends the last activity so a silently-dead connection gets noticed.
+    pub async fn heartbeat(&self) {
+        if self.suspended.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let Some(mut client) = self.try_get_client().await else {
+            return;
+        };
+        let last_activity = self.last_activity.lock().await;
+        let mut status = self.status.lock().await;
+
+        self.send_or_reconnect(&mut client, last_activity.as_ref(), &mut status);
+    }
+
+    /// Hides the presence and stops writing to Discord until resumed.
+    pub async fn suspend(&self) {
+        if self.suspended.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let Some(mut client) = self.try_get_client().await else {
+            return;
+        };
+
+        if let Err(e) = client.clear_activity() {
+            debug!("Failed to clear activity while suspending: {e}");
+            self.counters.error(&e);
+        }
+    }
+
+    /// Sends the newest activity, including the ones changed while suspended.
+    pub async fn resume(&self) {
+        if self.suspended.swap(false, Ordering::Relaxed) {
+            self.heartbeat().await;
+        }
+    }
+
+    /// Reconnects when the write fails and sends the activity again right away,
+    /// instead of leaving Discord without a presence until the next update.
+    fn send_or_reconnect(
         &self,
-        state: Option<String>,
-        details: Option<String>,
-        large_image: Option<String>,
-        large_text: Option<String>,
-        small_image: Option<String>,
-        small_text: Option<String>,
-        git_remote_url: Option<String>,
+        client: &mut IpcClient,
+        fields: Option<&ActivityFields>,
+        status: &mut ConnectionStatus,
     ) {
-        let mut client = self.get_client().await;
-        let timestamp: i64 = self.start_timestamp.as_millis() as i64;
+        if !self.ensure_connected(client, status) {
+            return;
+        }
+
+        let Err(e) = self.send_activity(client, fields) else {
+            self.set_status(status, ConnectionStatus::Connected);
+            return;
+        };
+
+        debug!("Failed to send activity, reconnecting: {e}");
+        self.counters.error(&e);
+        self.set_status(status, ConnectionStatus::Reconnecting);
+
+        if let Err(e) = client.connect() {
+            debug!("Failed to reconnect: {e}");
+            self.counters.error(&e);
+            self.set_status(status, ConnectionStatus::Disconnected);
+            return;
+        }
+
+        self.counters.reconnects.fetch_add(1, Ordering::Relaxed);
+        self.set_status(status, ConnectionStatus::Connected);
+
+        if let Err(e) = self.send_activity(client, fields) {
+            debug!("Failed to restore activity after reconnecting: {e}");
+            self.counters.error(&e);
+        }
+    }
+
+    /// Connects a client that's known to be disconnected before writing to it, e.g. when
+    /// Discord wasn't running on startup or the last reconnect failed as it was restarting.
+    /// Returns whether the client may be connected.
+    fn ensure_connected(&self, client: &mut IpcClient, status: &mut ConnectionStatus) -> bool {
+        if *status != ConnectionStatus::Disconnected {
+            return true;
+        }
+
+        if let Err(e) = client.connect() {
+            debug!("Discord is still unavailable: {e}");
+            self.counters.error(&e);
+            return false;
+        }
+
+        self.counters.reconnects.fetch_add(1, Ordering::Relaxed);
+        self.set_status(status, ConnectionStatus::Connected);
+        true
+    }
+
+    fn set_status(&self, current: &mut ConnectionStatus, status: ConnectionStatus) {
+        *current = status;
+        self.counters.status(status);
+    }
 
-        let activity = Activity::new()
-            .timestamps(Timestamps::new().start(timestamp))
-            .buttons(
-                git_remote_url
-                    .as_ref()
-                    .map(|url| vec![Button::new("View Repository", url)])
-                    .unwrap_or_default(),
-            );
+    pub async fn status(&self) -> ConnectionStatus {
+        *self.status.lock().await
+    }
 
-        let activity = util::set_optional_field(activity, state.as_deref(), Activity::state);
-        let activity = util::set_optional_field(activity, details.as_deref(), Activity::details);
+    pub async fn current_activity(&self) -> CurrentActivity {
+        CurrentActivity {
+            activity: self.last_activity.lock().await.clone(),
+            status: *self.status.lock().await,
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            updates_sent: self.counters.updates_sent.load(Ordering::Relaxed),
+            reconnects: self.counters.reconnects.load(Ordering::Relaxed),
+            last_error: self.counters.last_error.lock().unwrap().clone(),
+            uptime_secs: self
+                .counters
+                .connected_since
+                .lock()
+                .unwrap()
+                .map(|since| since.elapsed().as_secs()),
+        }
+    }
+
+    fn send_activity(
+        &self,
+        client: &mut IpcClient,
+        fields: Option<&ActivityFields>,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(fields) = fields else {
+            client.clear_activity()?;
+            self.counters.updates_sent.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        };
+
+        // Discord displays a countdown only when the end is set without a start,
+        // it keeps its length when the start is replaced
+        let start = fields.start.unwrap_or(self.start_timestamp);
+        let timestamps = match self.end_timestamp {
+            Some(end) => {
+                let end = start + end.saturating_sub(self.start_timestamp);
+                Timestamps::new().end(end.as_millis() as i64)
+            }
+            None => Timestamps::new().start(start.as_millis() as i64),
+        };
+
+        let activity = Activity::new().timestamps(timestamps).buttons(
+            fields
+                .git_remote_url
+                .as_ref()
+                .map(|url| {
+                    let label = fields.git_button_label.as_deref();
+                    vec![Button::new(label.unwrap_or("View Repository"), url)]
+                })
+                .unwrap_or_default(),
+        );
+
+        let activity = util::set_optional_field(activity, fields.state.as_deref(), Activity::state);
+        let activity =
+            util::set_optional_field(activity, fields.details.as_deref(), Activity::details);
 
         let assets = Assets::new();
-        let assets = util::set_optional_field(assets, large_image.as_deref(), Assets::large_image);
-        let assets = util::set_optional_field(assets, large_text.as_deref(), Assets::large_text);
-        let assets = util::set_optional_field(assets, small_image.as_deref(), Assets::small_image);
-        let assets = util::set_optional_field(assets, small_text.as_deref(), Assets::small_text);
+        let assets =
+            util::set_optional_field(assets, fields.large_image.as_deref(), Assets::large_image);
+        let assets =
+            util::set_optional_field(assets, fields.large_text.as_deref(), Assets::large_text);
+        let assets =
+            util::set_optional_field(assets, fields.small_image.as_deref(), Assets::small_image);
+        let assets =
+            util::set_optional_field(assets, fields.small_text.as_deref(), Assets::small_text);
 
         let activity = activity.assets(assets);
+        let activity = match fields.party_size {
+            Some(size) => activity.party(Party::new().size(size.map(|s| s as i32))),
+            None => activity,
+        };
+
+        client.set_activity(activity)?;
+        self.counters.updates_sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::{Arc, Mutex as StdMutex};
+
+    use super::*;
+
+    type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+    #[derive(Default)]
+    struct MockIpc {
+        client_id: String,
+        calls: Arc<StdMutex<Vec<String>>>,
+        failing_writes: usize,
+        failing_close: bool,
+    }
+
+    impl DiscordIpc for MockIpc {
+        fn get_client_id(&self) -> &String {
+            &self.client_id
+        }
+
+        fn connect(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push("connect".into());
+            Ok(())
+        }
+
+        fn connect_ipc(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<()> {
+            if self.failing_writes > 0 {
+                self.failing_writes -= 1;
+                return Err("broken pipe".into());
+            }
+
+            // Skip the binary header, only record the JSON payload
+            if let Ok(payload) = std::str::from_utf8(data) {
+                if payload.starts_with('{') {
+                    self.calls.lock().unwrap().push(payload.to_string());
+                }
+            }
+            Ok(())
+        }
+
+        fn read(&mut self, _buffer: &mut [u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push("close".into());
+            if self.failing_close {
+                return Err("Couldn't retrieve the Discord IPC socket".into());
+            }
+            Ok(())
+        }
+    }
+
+    fn discord_with_mock(failing_writes: usize) -> (Discord, Arc<StdMutex<Vec<String>>>) {
+        let mock = MockIpc {
+            failing_writes,
+            ..Default::default()
+        };
+        let calls = Arc::clone(&mock.calls);
+        let mut discord = Discord::new();
+        discord.client = Some(Mutex::new(Box::new(mock)));
+        // Like after connecting in `initialize`
+        *discord.status.get_mut() = ConnectionStatus::Connected;
+
+        (discord, calls)
+    }
+
+    #[tokio::test]
+    async fn test_kill_clears_activity_before_close() {
+        let (discord, calls) = discord_with_mock(0);
+
+        discord.kill().await;
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[0].contains("SET_ACTIVITY"));
+        assert!(calls[0].contains("\"activity\":null"));
+        assert_eq!(calls[1], "close");
+    }
+
+    #[tokio::test]
+    async fn test_kill_ignores_close_errors() {
+        let mock = MockIpc {
+            failing_close: true,
+            ..Default::default()
+        };
+        let calls = Arc::clone(&mock.calls);
+        let mut discord = Discord::new();
+        discord.client = Some(Mutex::new(Box::new(mock)));
+        *discord.status.get_mut() = ConnectionStatus::Connected;
+
+        discord.kill().await;
+
+        assert_eq!(calls.lock().unwrap().last().unwrap(), "close");
+        assert_eq!(*discord.status.lock().await, ConnectionStatus::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn test_kill_without_connection() {
+        let (mut discord, calls) = discord_with_mock(0);
+        *discord.status.get_mut() = ConnectionStatus::Disconnected;
+
+        discord.kill().await;
+
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_reconnects_and_restores_activity() {
+        let (discord, calls) = discord_with_mock(1);
+        *discord.last_activity.lock().await = Some(ActivityFields {
+            state: Some("Working on main.rs".into()),
+            ..Default::default()
+        });
+
+        discord.heartbeat().await;
+        assert_eq!(
+            discord.current_activity().await.status,
+            ConnectionStatus::Connected
+        );
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], "connect");
+        assert!(calls[1].contains("Working on main.rs"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_update_reconnects_and_resends() {
+        let (discord, calls) = discord_with_mock(1);
+
+        discord
+            .change_activity(ActivityFields {
+                state: Some("Idling".into()),
+                ..Default::default()
+            })
+            .await;
+
+        let current = discord.current_activity().await;
+        assert_eq!(current.status, ConnectionStatus::Connected);
+        assert_eq!(current.activity.unwrap().state.as_deref(), Some("Idling"));
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], "connect");
+        assert!(calls[1].contains("Idling"));
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_client_connects_before_sending() {
+        let (discord, calls) = discord_with_mock(0);
+        // Discord wasn't running on startup, or was restarted while the last reconnect failed
+        *discord.status.lock().await = ConnectionStatus::Disconnected;
+
+        discord
+            .change_activity(ActivityFields {
+                state: Some("Working on main.rs".into()),
+                ..Default::default()
+            })
+            .await;
+
+        assert_eq!(
+            discord.current_activity().await.status,
+            ConnectionStatus::Connected
+        );
+        assert_eq!(discord.stats().reconnects, 1);
+        assert_eq!(discord.stats().last_error, None);
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], "connect");
+        assert!(calls[1].contains("Working on main.rs"));
+    }
+
+    #[tokio::test]
+    async fn test_switching_applications() {
+        let (mut discord, calls) = discord_with_mock(0);
+        let shared = Arc::clone(&calls);
+        discord.new_client = Box::new(move |application_id| {
+            shared.lock().unwrap().push(format!("new {application_id}"));
+            Box::new(MockIpc {
+                client_id: application_id.to_string(),
+                calls: Arc::clone(&shared),
+                ..Default::default()
+            })
+        });
+        discord.create_client(String::from("main"));
+        calls.lock().unwrap().clear();
+
+        let fields = |state: &str, application_id: Option<&str>| ActivityFields {
+            state: Some(state.into()),
+            application_id: application_id.map(String::from),
+            ..Default::default()
+        };
+
+        discord
+            .change_activity(fields("Idling", Some("idle")))
+            .await;
+        discord
+            .change_activity(fields("Still idling", Some("idle")))
+            .await;
+        discord.change_activity(fields("Editing", None)).await;
+
+        let calls = calls.lock().unwrap();
+        let sequence: Vec<&str> = calls
+            .iter()
+            .map(|call| match call.as_str() {
+                call if call.contains("\"activity\":null") => "clear",
+                call if call.contains("\"state\":") => "set",
+                call => call,
+            })
+            .collect();
+        assert_eq!(
+            sequence,
+            [
+                "clear", "close", "new idle", "connect", "set", "set", "clear", "close",
+                "new main", "connect", "set"
+            ]
+        );
+        assert!(calls[4].contains("Idling"));
+        assert!(calls[10].contains("Editing"));
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let (discord, _) = discord_with_mock(1);
+        discord.heartbeat().await;
+        discord.heartbeat().await;
+
+        let stats = discord.stats();
+        assert_eq!(stats.updates_sent, 2);
+        assert_eq!(stats.reconnects, 1);
+        assert!(stats.last_error.is_some());
+        assert_eq!(stats.uptime_secs, Some(0));
+
+        discord.kill().await;
+        assert_eq!(discord.stats().uptime_secs, None);
+    }
+
+    #[tokio::test]
+    async fn test_suspend_and_resume() {
+        let (discord, calls) = discord_with_mock(0);
+        let fields = |state: &str| ActivityFields {
+            state: Some(state.into()),
+            ..Default::default()
+        };
+
+        discord.change_activity(fields("Editing main.rs")).await;
+        discord.suspend().await;
+        assert_eq!(calls.lock().unwrap().len(), 2);
+        assert!(calls.lock().unwrap()[1].contains("\"activity\":null"));
+
+        discord.change_activity(fields("Idling")).await;
+        discord.heartbeat().await;
+        assert_eq!(calls.lock().unwrap().len(), 2);
+
+        discord.resume().await;
+        discord.resume().await;
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert!(calls[2].contains("Idling"));
+    }
+
+    #[tokio::test]
+    async fn test_end_timestamp() {
+        let (mut discord, calls) = discord_with_mock(0);
+        discord.set_end_in(Some(25 * 60));
+
+        let end = discord.start_timestamp + Duration::from_secs(25 * 60);
+        assert_eq!(discord.end_timestamp, Some(end));
+
+        discord
+            .change_activity(ActivityFields {
+                state: Some("Focusing".into()),
+                ..Default::default()
+            })
+            .await;
+
+        let calls = calls.lock().unwrap();
+        assert!(calls[0].contains(&format!("\"end\":{}", end.as_millis())));
+        assert!(!calls[0].contains("\"start\""));
+    }
+
+    #[tokio::test]
+    async fn test_party() {
+        let (discord, calls) = discord_with_mock(0);
+
+        discord
+            .change_activity(ActivityFields {
+                state: Some("Pairing".into()),
+                party_size: Some([1, 3]),
+                ..Default::default()
+            })
+            .await;
+        discord
+            .change_activity(ActivityFields {
+                state: Some("Alone".into()),
+                ..Default::default()
+            })
+            .await;
+
+        let calls = calls.lock().unwrap();
+        assert!(calls[0].contains("\"party\":{\"size\":[1,3]}"));
+        assert!(!calls[1].contains("party"));
+    }
+
+    #[tokio::test]
+    async fn test_empty_activity_is_cleared() {
+        let (discord, calls) = discord_with_mock(0);
+
+        discord.change_activity(ActivityFields::default()).await;
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0].contains("\"activity\":null"));
+    }
+
+    #[tokio::test]
+    async fn test_current_activity() {
+        let (discord, _) = discord_with_mock(0);
+        discord.connect().await.unwrap();
+        discord
+            .change_activity(ActivityFields {
+                details: Some("In zed-discord-presence".into()),
+                ..Default::default()
+            })
+            .await;
+
+        let current = serde_json::to_value(discord.current_activity().await).unwrap();
+        assert_eq!(current["status"], "connected");
+        assert_eq!(current["activity"]["details"], "In zed-discord-presence");
+        assert!(current["activity"]["state"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_held_client_times_out() {
+        let (mut discord, calls) = discord_with_mock(0);
+        discord.lock_timeout = Duration::from_millis(10);
+
+        let held = discord.get_client().await.unwrap();
+        assert!(discord.get_client().await.is_err());
+
+        discord
+            .change_activity(ActivityFields {
+                state: Some("Working on main.rs".into()),
+                ..Default::default()
+            })
+            .await;
+        drop(held);
+
+        let current = discord.current_activity().await;
+        assert_eq!(current.status, ConnectionStatus::Reconnecting);
+        assert!(current.activity.is_some());
+        assert!(calls.lock().unwrap().is_empty());
 
-        client
-            .set_activity(activity)
-            .unwrap_or_else(|_| println!("Failed to set activity with activity"));
+        discord.heartbeat().await;
+        assert!(calls.lock().unwrap()[0].contains("Working on main.rs"));
+        assert_eq!(
+            discord.current_activity().await.status,
+            ConnectionStatus::Connected
+        );
     }
 }
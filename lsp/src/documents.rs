@@ -0,0 +1,147 @@
+/*
+ * This file is part of discord-presence. Extension for Zed that adds support for Discord Rich Presence using LSP.
+ *
+ * Copyright (c) 2024 Steinhübl
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use tower_lsp::lsp_types::Url;
+
+use crate::Document;
+
+/// Open documents ordered from the most recently active one.
+#[derive(Debug, Default)]
+pub struct OpenDocuments {
+    documents: Vec<Url>,
+    shebangs: HashMap<Url, String>,
+    dirty: HashSet<Url>, // changed since they were opened or last saved
+}
+
+impl OpenDocuments {
+    pub fn push(&mut self, url: Url) {
+        self.documents.retain(|document| document != &url);
+        self.documents.insert(0, url);
+    }
+
+    /// Removes the document and returns whether it was the active one.
+    pub fn close(&mut self, url: &Url) -> bool {
+        let was_active = self.active() == Some(url);
+        self.documents.retain(|document| document != url);
+        self.shebangs.remove(url);
+        self.dirty.remove(url);
+
+        was_active
+    }
+
+    pub fn active(&self) -> Option<&Url> {
+        self.documents.first()
+    }
+
+    /// Remembers the shebang from the first line of the document's text, if it has one.
+    pub fn set_shebang(&mut self, url: &Url, text: &str) {
+        match text.lines().next().filter(|line| line.starts_with("#!")) {
+            Some(line) => self.shebangs.insert(url.clone(), line.to_string()),
+            None => self.shebangs.remove(url),
+        };
+    }
+
+    /// Marks the document as changed by `didChange`, or as saved by `didSave`.
+    pub fn set_dirty(&mut self, url: &Url, dirty: bool) {
+        if dirty {
+            self.dirty.insert(url.clone());
+        } else {
+            self.dirty.remove(url);
+        }
+    }
+
+    pub fn document(&self, url: Url) -> Document {
+        let shebang = self.shebangs.get(&url).cloned();
+        let dirty = self.dirty.contains(&url);
+        Document::new(url).with_shebang(shebang).with_dirty(dirty)
+    }
+
+    pub fn active_document(&self) -> Option<Document> {
+        self.active().cloned().map(|url| self.document(url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(path: &str) -> Url {
+        Url::parse(&format!("file:///home/user/{path}")).unwrap()
+    }
+
+    #[test]
+    fn test_close_active_with_others() {
+        let mut documents = OpenDocuments::default();
+        documents.push(url("a.rs"));
+        documents.push(url("b.rs"));
+        documents.push(url("c.rs"));
+        documents.push(url("a.rs"));
+
+        assert!(documents.close(&url("a.rs")));
+        assert_eq!(documents.active(), Some(&url("c.rs")));
+
+        assert!(!documents.close(&url("b.rs")));
+        assert_eq!(documents.active(), Some(&url("c.rs")));
+    }
+
+    #[test]
+    fn test_close_last() {
+        let mut documents = OpenDocuments::default();
+        documents.push(url("a.rs"));
+
+        assert!(documents.close(&url("a.rs")));
+        assert_eq!(documents.active(), None);
+    }
+
+    #[test]
+    fn test_shebang() {
+        let mut documents = OpenDocuments::default();
+        documents.set_shebang(&url("script"), "#!/usr/bin/env python3\nprint()\n");
+        documents.set_shebang(&url("notes"), "hello\n#!/bin/sh\n");
+        documents.push(url("script"));
+
+        assert_eq!(
+            documents.active_document().unwrap().shebang.as_deref(),
+            Some("#!/usr/bin/env python3")
+        );
+        assert_eq!(documents.document(url("notes")).shebang, None);
+
+        documents.close(&url("script"));
+        assert_eq!(documents.document(url("script")).shebang, None);
+    }
+
+    #[test]
+    fn test_dirty() {
+        let mut documents = OpenDocuments::default();
+        documents.push(url("a.rs"));
+        assert!(!documents.active_document().unwrap().dirty);
+
+        documents.set_dirty(&url("a.rs"), true);
+        assert!(documents.active_document().unwrap().dirty);
+
+        documents.set_dirty(&url("a.rs"), false);
+        assert!(!documents.active_document().unwrap().dirty);
+
+        documents.set_dirty(&url("a.rs"), true);
+        documents.close(&url("a.rs"));
+        assert!(!documents.document(url("a.rs")).dirty);
+    }
+}
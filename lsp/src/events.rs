@@ -0,0 +1,223 @@
+/*
+ * This file is part of discord-presence. Extension for Zed that adds support for Discord Rich Presence using LSP.
+ *
+ * Copyright (c) 2024 Steinhübl
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>
+ */
+
+use std::io;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+/// Publishes every presence update as a JSON line to the readers connected to
+/// `events.socket_path`, a Unix socket or a named pipe on Windows.
+#[derive(Debug)]
+pub struct EventSocket {
+    path: String,
+    events: watch::Sender<String>, // the last event, sent first to readers that connect later
+    listener: JoinHandle<()>,
+}
+
+impl EventSocket {
+    /// Starts accepting readers at `path`, a stale socket left by a crash is replaced.
+    /// Anything else at `path`, e.g. the socket of another window, fails with `AddrInUse`.
+    #[cfg(unix)]
+    pub fn listen(path: &str) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixStream;
+        use tokio::net::UnixListener;
+
+        let stale = std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+            && UnixStream::connect(path).is_err();
+        if stale {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let (events, _) = watch::channel(String::new());
+
+        let sender = events.clone();
+        let listener = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(forward(stream, sender.subscribe()));
+                    }
+                    Err(e) => debug!("Failed to accept an events reader: {e}"),
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_string(),
+            events,
+            listener,
+        })
+    }
+
+    /// Starts accepting readers at the pipe `path`, e.g. `\\.\pipe\discord-presence`.
+    #[cfg(windows)]
+    pub fn listen(path: &str) -> io::Result<Self> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let mut server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(path)?;
+        let (events, _) = watch::channel(String::new());
+
+        let sender = events.clone();
+        let pipe = path.to_string();
+        let listener = tokio::spawn(async move {
+            loop {
+                if let Err(e) = server.connect().await {
+                    debug!("Failed to accept an events reader: {e}");
+                    continue;
+                }
+
+                // Every reader needs its own instance of the pipe
+                let next = match ServerOptions::new().create(&pipe) {
+                    Ok(next) => next,
+                    Err(e) => {
+                        debug!("Failed to create the events pipe {pipe}: {e}");
+                        return;
+                    }
+                };
+                tokio::spawn(forward(
+                    std::mem::replace(&mut server, next),
+                    sender.subscribe(),
+                ));
+            }
+        });
+
+        Ok(Self {
+            path: path.to_string(),
+            events,
+            listener,
+        })
+    }
+
+    pub fn publisher(&self) -> EventPublisher {
+        EventPublisher(self.events.clone())
+    }
+
+    /// Stops accepting readers and removes the socket, readers get an end of file.
+    pub fn close(self) {
+        self.listener.abort();
+        debug!("Closed the events socket {}", self.path);
+
+        #[cfg(unix)]
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            debug!("Failed to remove the events socket {}: {e}", self.path);
+        }
+    }
+}
+
+/// Sends events to the readers of an `EventSocket`.
+#[derive(Debug, Clone)]
+pub struct EventPublisher(watch::Sender<String>);
+
+impl EventPublisher {
+    pub fn publish(&self, event: &impl serde::Serialize) {
+        match serde_json::to_string(event) {
+            Ok(line) => {
+                self.0.send_replace(line + "\n");
+            }
+            Err(e) => debug!("Failed to serialize an event: {e}"),
+        }
+    }
+}
+
+/// Writes the events to a reader until it disconnects or the socket is closed.
+async fn forward(mut stream: impl AsyncWrite + Unpin, mut events: watch::Receiver<String>) {
+    loop {
+        let line = events.borrow_and_update().clone();
+        if !line.is_empty() && stream.write_all(line.as_bytes()).await.is_err() {
+            return;
+        }
+
+        if events.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::env;
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+    use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+    use tokio::net::UnixStream;
+    use tokio::time;
+
+    use super::*;
+
+    async fn next_event(lines: &mut Lines<BufReader<UnixStream>>) -> Value {
+        let line = time::timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_readers_get_events() {
+        let path = env::temp_dir().join("discord-presence-lsp-test-events.sock");
+        let path = path.to_str().unwrap();
+        let socket = EventSocket::listen(path).unwrap();
+        let publisher = socket.publisher();
+        publisher.publish(&json!({ "state": "first" }));
+
+        let stream = UnixStream::connect(path).await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+
+        // The last event is sent right away
+        assert_eq!(next_event(&mut lines).await["state"], "first");
+
+        publisher.publish(&json!({ "state": "second" }));
+        assert_eq!(next_event(&mut lines).await["state"], "second");
+
+        socket.close();
+        assert!(std::fs::metadata(path).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_existing_paths() {
+        let path = env::temp_dir().join("discord-presence-lsp-test-events-existing.sock");
+        let path = path.to_str().unwrap();
+
+        // Another window still listens on it
+        let socket = EventSocket::listen(path).unwrap();
+        let err = EventSocket::listen(path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(UnixStream::connect(path).await.is_ok());
+
+        // Left behind by a crash
+        socket.listener.abort();
+        time::sleep(Duration::from_millis(50)).await;
+        let socket = EventSocket::listen(path).unwrap();
+        socket.close();
+
+        std::fs::write(path, "not a socket").unwrap();
+        let err = EventSocket::listen(path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "not a socket");
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -17,13 +17,12 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>
  */
 
+use std::path::{Path, PathBuf};
+
 use git2::Repository;
 
 fn get_repository(path: &str) -> Option<Repository> {
-    match Repository::open(path) {
-        Ok(repo) => Some(repo),
-        Err(_) => None,
-    }
+    Repository::open(path).ok()
 }
 
 fn get_main_remote_url(repository: Repository) -> Option<String> {
@@ -56,9 +55,317 @@ fn transform_url(url: String) -> String {
     url.to_string()
 }
 
+/// Returns the host of a remote url, e.g. `github.com`
+pub fn get_host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.split_once('@').map_or(rest, |(_, host)| host);
+
+    rest.split(['/', ':'])
+        .next()
+        .filter(|host| !host.is_empty())
+}
+
+/// Returns the repository name of a remote url, e.g. `zed` for `git@github.com:zed-industries/zed.git`
+pub fn get_repository_name(url: &str) -> Option<&str> {
+    let name = url.trim_end_matches('/').rsplit(['/', ':']).next()?;
+    let name = name.strip_suffix(".git").unwrap_or(name);
+
+    Some(name).filter(|name| !name.is_empty())
+}
+
 pub fn get_repository_and_remote(path: &str) -> Option<String> {
     match get_repository(path) {
         Some(repository) => get_main_remote_url(repository),
         None => None,
     }
 }
+
+/// Returns the path of `HEAD`, which lives outside of the workspace for worktrees
+pub fn get_head_path(path: &str) -> Option<PathBuf> {
+    get_repository(path).map(|repository| repository.path().join("HEAD"))
+}
+
+/// Whether a `.gitignore` of the repository at `path` ignores the file, files outside
+/// of a repository are never ignored.
+pub fn is_ignored(path: &str, file: &Path) -> bool {
+    let Some(repository) = get_repository(path) else {
+        return false;
+    };
+    let Some(relative) = repository
+        .workdir()
+        .and_then(|workdir| file.strip_prefix(workdir).ok())
+    else {
+        return false;
+    };
+
+    repository.is_path_ignored(relative).unwrap_or(false)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GitStatus {
+    pub branch: Option<String>,
+    pub ahead_behind: Option<(usize, usize)>, // relative to the upstream branch
+}
+
+pub fn get_status(path: &str) -> GitStatus {
+    let Some(repository) = get_repository(path) else {
+        return GitStatus::default();
+    };
+
+    GitStatus {
+        branch: get_branch(&repository),
+        ahead_behind: get_ahead_behind(&repository),
+    }
+}
+
+fn get_ahead_behind(repository: &Repository) -> Option<(usize, usize)> {
+    let head = repository.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+
+    let branch = git2::Branch::wrap(head);
+    let upstream = branch.upstream().ok()?;
+
+    repository
+        .graph_ahead_behind(branch.get().target()?, upstream.get().target()?)
+        .ok()
+}
+
+/// Returns the branch name, the tag name when HEAD is detached at a tag,
+/// or the short commit hash when it's detached elsewhere.
+fn get_branch(repository: &Repository) -> Option<String> {
+    let head = repository.head().ok()?;
+
+    if head.is_branch() {
+        return head.shorthand().map(ToString::to_string);
+    }
+
+    let commit = head.peel_to_commit().ok()?;
+    let tag = repository
+        .references_glob("refs/tags/*")
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|tag| tag.peel_to_commit().is_ok_and(|c| c.id() == commit.id()));
+
+    if let Some(tag) = tag {
+        return tag.shorthand().map(ToString::to_string);
+    }
+
+    let short_id = commit.as_object().short_id().ok()?;
+    short_id.as_str().map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use git2::{Oid, Signature};
+
+    use super::*;
+
+    fn temp_repository(name: &str) -> (PathBuf, Repository) {
+        let path = env::temp_dir().join(format!("discord-presence-lsp-test-{name}"));
+        let _ = fs::remove_dir_all(&path);
+
+        let repository = Repository::init(&path).unwrap();
+        (path, repository)
+    }
+
+    fn commit(repository: &Repository, message: &str) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree_id = repository.index().unwrap().write_tree().unwrap();
+        let tree = repository.find_tree(tree_id).unwrap();
+        let parent = repository.head().ok().and_then(|h| h.peel_to_commit().ok());
+        let parents: Vec<_> = parent.iter().collect();
+
+        repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_get_repository_name() {
+        for url in [
+            "https://github.com/xhyrom/zed-discord-presence",
+            "https://github.com/xhyrom/zed-discord-presence.git",
+            "https://github.com/xhyrom/zed-discord-presence/",
+            "git@github.com:xhyrom/zed-discord-presence.git",
+            "ssh://git@git.example.com:2222/xhyrom/zed-discord-presence.git",
+            "/srv/git/zed-discord-presence.git",
+        ] {
+            assert_eq!(
+                get_repository_name(url),
+                Some("zed-discord-presence"),
+                "{url}"
+            );
+        }
+
+        assert_eq!(get_repository_name(""), None);
+        assert_eq!(get_repository_name("https://example.com/.git"), None);
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let (path, _) = temp_repository("ignored");
+        fs::write(path.join(".gitignore"), "target/\n*.log\n").unwrap();
+        let repository_path = path.to_str().unwrap();
+
+        assert!(is_ignored(
+            repository_path,
+            &path.join("target/debug/build.rs")
+        ));
+        assert!(is_ignored(repository_path, &path.join("server.log")));
+        assert!(!is_ignored(repository_path, &path.join("src/main.rs")));
+        assert!(!is_ignored(
+            repository_path,
+            Path::new("/usr/lib/rust/target/vec.rs")
+        ));
+
+        fs::remove_dir_all(&path).unwrap();
+        assert!(!is_ignored(
+            repository_path,
+            &path.join("target/debug/build.rs")
+        ));
+    }
+
+    #[test]
+    fn test_get_branch() {
+        let (path, repository) = temp_repository("branch");
+        commit(&repository, "initial");
+        let head = repository.head().unwrap().peel_to_commit().unwrap();
+        repository.branch("feature", &head, false).unwrap();
+        repository.set_head("refs/heads/feature").unwrap();
+
+        assert_eq!(
+            get_status(path.to_str().unwrap()).branch.as_deref(),
+            Some("feature")
+        );
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_get_branch_detached() {
+        let (path, repository) = temp_repository("detached");
+        let first = commit(&repository, "first");
+        commit(&repository, "second");
+        repository.set_head_detached(first).unwrap();
+
+        let short_id = repository
+            .find_object(first, None)
+            .unwrap()
+            .short_id()
+            .unwrap();
+        assert_eq!(
+            get_status(path.to_str().unwrap()).branch.as_deref(),
+            short_id.as_str()
+        );
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_get_branch_tag() {
+        let (path, repository) = temp_repository("tag");
+        let first = commit(&repository, "first");
+        commit(&repository, "second");
+        let object = repository.find_object(first, None).unwrap();
+        repository
+            .tag_lightweight("v1.0.0", &object, false)
+            .unwrap();
+        repository.set_head_detached(first).unwrap();
+
+        assert_eq!(
+            get_status(path.to_str().unwrap()).branch.as_deref(),
+            Some("v1.0.0")
+        );
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_get_host() {
+        assert_eq!(
+            get_host("https://github.com/xhyrom/zed-discord-presence"),
+            Some("github.com")
+        );
+        assert_eq!(
+            get_host("https://user@git.example.com:8443/repo"),
+            Some("git.example.com")
+        );
+        assert_eq!(
+            get_host("git@gitlab.com:group/repo.git"),
+            Some("gitlab.com")
+        );
+        assert_eq!(get_host(""), None);
+    }
+
+    #[test]
+    fn test_get_ahead_behind() {
+        let (path, repository) = temp_repository("ahead-behind");
+        let base = commit(&repository, "base");
+        repository
+            .remote("origin", "https://example.com/repo.git")
+            .unwrap();
+
+        // Remote is one commit ahead of the base, the local branch another one
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let base_commit = repository.find_commit(base).unwrap();
+        let remote = repository
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "remote",
+                &base_commit.tree().unwrap(),
+                &[&base_commit],
+            )
+            .unwrap();
+        repository
+            .reference("refs/remotes/origin/main", remote, true, "")
+            .unwrap();
+        commit(&repository, "local");
+
+        let head = repository.head().unwrap();
+        let mut branch = git2::Branch::wrap(head);
+        assert_eq!(get_status(path.to_str().unwrap()).ahead_behind, None);
+
+        branch.set_upstream(Some("origin/main")).unwrap();
+        assert_eq!(
+            get_status(path.to_str().unwrap()).ahead_behind,
+            Some((1, 1))
+        );
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_get_head_path_worktree() {
+        let (path, repository) = temp_repository("worktree");
+        commit(&repository, "initial");
+        let worktree_path = env::temp_dir().join("discord-presence-lsp-test-worktree-checkout");
+        let _ = fs::remove_dir_all(&worktree_path);
+        repository
+            .worktree("checkout", &worktree_path, None)
+            .unwrap();
+
+        let head = get_head_path(worktree_path.to_str().unwrap()).unwrap();
+        assert!(head.ends_with("worktrees/checkout/HEAD"));
+        assert!(head.exists());
+
+        let head = get_head_path(path.to_str().unwrap()).unwrap();
+        assert!(head.ends_with(".git/HEAD"));
+
+        fs::remove_dir_all(worktree_path).unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+}
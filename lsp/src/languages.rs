@@ -1,11 +1,42 @@
 use lazy_static::lazy_static;
 use regex::RegexBuilder;
 use serde_json::from_str;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
 use std::sync::Mutex;
 
+use crate::configuration::Configuration;
 use crate::Document;
 
+/// Languages of data and documentation files, they don't count towards `{project_language}`
+const NON_CODE_LANGUAGES: &[&str] = &[
+    "hjson",
+    "json",
+    "log",
+    "markdown",
+    "restructuredtext",
+    "svg",
+    "text",
+    "toml",
+    "xml",
+    "yaml",
+];
+
+/// Dependency and build output directories skipped when scanning the workspace
+const IGNORED_DIRS: &[&str] = &[
+    "__pycache__",
+    "build",
+    "dist",
+    "node_modules",
+    "out",
+    "target",
+    "vendor",
+];
+
+/// Files looked at before the most common language of the workspace is picked
+const PROJECT_SCAN_LIMIT: usize = 500;
+
 lazy_static! {
     static ref LANGUAGE_MAP: Mutex<HashMap<String, String>> = {
         let data = include_str!("../../assets/languages.json");
@@ -14,12 +45,21 @@ lazy_static! {
     };
 }
 
-pub fn get_language(document: &Document) -> String {
+/// Returns the language of a document, `language_by_path` takes precedence over its filename,
+/// extension and shebang.
+pub fn document_language(document: &Document, config: &Configuration) -> String {
+    config.language_override(&document.path).map_or_else(
+        || get_language(document, &config.default_language),
+        str::to_string,
+    )
+}
+
+pub fn get_language(document: &Document, default_language: &str) -> String {
     let map = LANGUAGE_MAP.lock().unwrap();
     let filename = document.get_filename().to_string();
     let extension = format!(".{}", document.get_extension());
 
-    if let Some(s) = map.get(&filename) {
+    if let Some(s) = lookup(&map, &filename) {
         return s.to_string();
     }
 
@@ -39,11 +79,117 @@ pub fn get_language(document: &Document) -> String {
         }
     }
 
-    if let Some(s) = map.get(&extension) {
+    if let Some(s) = lookup(&map, &extension) {
         return s.to_string();
     }
 
-    String::from("text")
+    if let Some(language) = document.shebang.as_deref().and_then(shebang_language) {
+        return language.to_string();
+    }
+
+    default_language.to_string()
+}
+
+/// Looks up a literal key, preferring an exact match over one differing only in case.
+fn lookup<'a>(map: &'a HashMap<String, String>, key: &str) -> Option<&'a String> {
+    map.get(key).or_else(|| {
+        map.iter()
+            .find(|(k, _)| !k.starts_with("regex:") && k.eq_ignore_ascii_case(key))
+            .map(|(_, language)| language)
+    })
+}
+
+/// Maps the interpreter of a `#!` line, e.g. `#!/usr/bin/env python3`, to its language.
+fn shebang_language(shebang: &str) -> Option<&'static str> {
+    let mut args = shebang.strip_prefix("#!")?.split_whitespace();
+    let mut interpreter = args.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        // Skip flags such as `env -S`
+        interpreter = args.find(|arg| !arg.starts_with('-'))?;
+    }
+
+    // `python3.12` and `python3` are both python
+    let name = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+    Some(match name {
+        "python" | "pypy" => "python",
+        "sh" | "bash" | "zsh" | "dash" | "ksh" | "mksh" | "fish" => "shell",
+        "node" | "nodejs" | "deno" | "bun" => "js",
+        "ruby" => "ruby",
+        "perl" => "perl",
+        "php" => "php",
+        "lua" | "luajit" => "lua",
+        _ => return None,
+    })
+}
+
+/// Returns the most common language among the source files of the workspace, looking at the
+/// first files breadth-first so the top-level directories weigh the most. Hidden and ignored
+/// directories are skipped, ties go to the alphabetically first language.
+pub fn project_language(root: &Path) -> Option<String> {
+    let map = LANGUAGE_MAP.lock().unwrap();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut directories = VecDeque::from([root.to_path_buf()]);
+    let mut scanned = 0;
+
+    'scan: while let Some(directory) = directories.pop_front() {
+        let Ok(entries) = fs::read_dir(&directory) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') {
+                continue;
+            }
+
+            // Symlinks aren't followed, they could loop
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if !IGNORED_DIRS.contains(&name.as_ref()) {
+                    directories.push_back(entry.path());
+                }
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+
+            scanned += 1;
+            if scanned > PROJECT_SCAN_LIMIT {
+                break 'scan;
+            }
+
+            let language = Path::new(name.as_ref())
+                .extension()
+                .and_then(|extension| lookup(&map, &format!(".{}", extension.to_string_lossy())))
+                .filter(|language| !NON_CODE_LANGUAGES.contains(&language.as_str()));
+            if let Some(language) = language {
+                *counts.entry(language).or_default() += 1;
+            }
+        }
+    }
+
+    counts
+        .into_iter()
+        .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then_with(|| b.cmp(a)))
+        .map(|(language, _)| language.to_string())
+}
+
+/// Returns the distinct languages that can be detected, including the ones forced by
+/// `language_by_path`, sorted.
+pub fn get_languages(config: &Configuration) -> Vec<String> {
+    let map = LANGUAGE_MAP.lock().unwrap();
+
+    map.values()
+        .chain(config.language_by_path.iter().map(|(_, language)| language))
+        .cloned()
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect()
 }
 
 #[cfg(test)]
@@ -55,7 +201,132 @@ mod tests {
     #[test]
     fn test_unicode_perl() {
         let document = Document::new(Url::parse("file:///home/user/file.php").unwrap());
-        let lang = get_language(&document);
+        let lang = get_language(&document, "text");
         assert_eq!(lang, "php");
     }
+
+    #[test]
+    fn test_default_language() {
+        let document = Document::new(Url::parse("file:///home/user/file.unknownext").unwrap());
+
+        assert_eq!(get_language(&document, "text"), "text");
+        assert_eq!(get_language(&document, "code"), "code");
+    }
+
+    #[test]
+    fn test_case_insensitive_filename() {
+        let manifest = Document::new(Url::parse("file:///app/AndroidManifest.xml").unwrap());
+        let lowercase = Document::new(Url::parse("file:///app/androidmanifest.xml").unwrap());
+
+        assert_eq!(get_language(&manifest, "text"), "android");
+        assert_eq!(get_language(&lowercase, "text"), "android");
+    }
+
+    #[test]
+    fn test_case_insensitive_extension() {
+        let document = Document::new(Url::parse("file:///home/user/MAIN.RS").unwrap());
+        assert_eq!(get_language(&document, "text"), "rust");
+    }
+
+    #[test]
+    fn test_shebang() {
+        let script = |shebang: &str| {
+            Document::new(Url::parse("file:///home/user/bin/script").unwrap())
+                .with_shebang(Some(shebang.to_string()))
+        };
+
+        assert_eq!(
+            get_language(&script("#!/usr/bin/env python3"), "text"),
+            "python"
+        );
+        assert_eq!(get_language(&script("#!/bin/bash -e"), "text"), "shell");
+        assert_eq!(
+            get_language(&script("#!/usr/bin/env -S node --no-warnings"), "text"),
+            "js"
+        );
+        assert_eq!(get_language(&script("#!/usr/bin/ruby"), "text"), "ruby");
+        assert_eq!(
+            get_language(&script("#!/usr/bin/env unknown"), "text"),
+            "text"
+        );
+    }
+
+    #[test]
+    fn test_extension_before_shebang() {
+        let document = Document::new(Url::parse("file:///home/user/main.rs").unwrap())
+            .with_shebang(Some(String::from("#!/usr/bin/env python3")));
+
+        assert_eq!(get_language(&document, "text"), "rust");
+    }
+
+    #[test]
+    fn test_language_by_path() {
+        let mut config = Configuration::new();
+        config.set_workspace_path("/home/user/project");
+        config.language_by_path = vec![
+            (String::from("src/weird.txt"), String::from("sql")),
+            (String::from("queries/**/*.txt"), String::from("sql")),
+            (String::from("*.inc"), String::from("php")),
+        ];
+        let language = |path: &str| {
+            let document = Document::new(Url::parse(&format!("file://{path}")).unwrap());
+            document_language(&document, &config)
+        };
+
+        assert_eq!(language("/home/user/project/src/weird.txt"), "sql");
+        assert_eq!(language("/home/user/project/src/other.txt"), "text");
+        assert_eq!(language("/home/user/project/queries/users/all.txt"), "sql");
+        assert_eq!(language("/home/user/project/lib/header.inc"), "php");
+        assert_eq!(language("/home/user/other/src/weird.txt"), "text");
+    }
+
+    #[test]
+    fn test_get_languages_sorted_and_distinct() {
+        let mut config = Configuration::new();
+        config.language_by_path = vec![
+            (String::from("docs/**"), String::from("zzz-docs")),
+            (String::from("*.tmpl"), String::from("rust")),
+        ];
+        let languages = get_languages(&config);
+
+        assert!(languages.contains(&String::from("rust")));
+        assert!(languages.contains(&String::from("zzz-docs")));
+        assert!(languages.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_project_language() {
+        let root = std::env::temp_dir().join("discord-presence-lsp-test-project-language");
+        let _ = fs::remove_dir_all(&root);
+        for directory in ["src/bin", "node_modules/dep", ".git/hooks", "config"] {
+            fs::create_dir_all(root.join(directory)).unwrap();
+        }
+        for file in [
+            "src/main.rs",
+            "src/lib.rs",
+            "src/bin/tool.rs",
+            "build.py",
+            "README.md",
+            "config/a.json",
+            "config/b.json",
+            "config/c.json",
+            "config/d.json",
+            "node_modules/dep/a.js",
+            "node_modules/dep/b.js",
+            "node_modules/dep/c.js",
+            "node_modules/dep/d.js",
+            ".git/hooks/a.sh",
+            ".git/hooks/b.sh",
+            ".git/hooks/c.sh",
+            ".git/hooks/d.sh",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        assert_eq!(project_language(&root).as_deref(), Some("rust"));
+        assert_eq!(project_language(&root.join("config")), None);
+        assert_eq!(project_language(&root.join("missing")), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,51 @@
+/*
+ * This file is part of discord-presence. Extension for Zed that adds support for Discord Rich Presence using LSP.
+ *
+ * Copyright (c) 2024 Steinhübl
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>
+ */
+
+/// Returns whether the screen is locked, `None` when it can't be detected on this platform.
+#[cfg(target_os = "linux")]
+pub fn is_screen_locked() -> Option<bool> {
+    let output = std::process::Command::new("loginctl")
+        .args(["show-session", "--property=LockedHint", "--value"])
+        .arg(std::env::var("XDG_SESSION_ID").ok()?)
+        .output()
+        .ok()?;
+
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns whether the screen is locked, `None` when it can't be detected on this platform.
+#[cfg(target_os = "macos")]
+pub fn is_screen_locked() -> Option<bool> {
+    let output = std::process::Command::new("ioreg")
+        .args(["-n", "Root", "-d1"])
+        .output()
+        .ok()?;
+
+    Some(String::from_utf8_lossy(&output.stdout).contains("\"CGSSessionScreenIsLocked\"=Yes"))
+}
+
+/// Returns whether the screen is locked, `None` when it can't be detected on this platform.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn is_screen_locked() -> Option<bool> {
+    None
+}
@@ -0,0 +1,299 @@
+/*
+ * This file is part of discord-presence. Extension for Zed that adds support for Discord Rich Presence using LSP.
+ *
+ * Copyright (c) 2024 Steinhübl
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>
+ */
+
+use std::cmp::Reverse;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter};
+use tracing_subscriber::{Layer, Registry};
+
+const LOG_PREFIX: &str = "discord-presence-lsp";
+const DEFAULT_MAX_FILES: usize = 10;
+
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    fn from_env() -> Self {
+        match env::var("DISCORD_PRESENCE_LOG_FORMAT").as_deref() {
+            Ok("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum LogRotation {
+    Daily,
+    Hourly,
+    Size(u64), // in bytes
+}
+
+impl LogRotation {
+    fn parse(rotation: &str) -> Self {
+        match rotation {
+            "hourly" => LogRotation::Hourly,
+            _ => rotation
+                .strip_prefix("size:")
+                .and_then(|bytes| bytes.parse().ok())
+                .map_or(LogRotation::Daily, LogRotation::Size),
+        }
+    }
+}
+
+/// Logs to stderr and to a log file in the data directory, filtered by `RUST_LOG`.
+///
+/// The returned guard flushes the log file when dropped, so it has to be kept alive.
+pub fn init() -> Option<WorkerGuard> {
+    let format = LogFormat::from_env();
+    let mut layers = vec![format_layer(format, std::io::stderr)];
+
+    let guard = log_directory().and_then(|directory| {
+        let writer = file_writer(&directory)
+            .map_err(|e| eprintln!("Failed to open log file in {}: {e}", directory.display()))
+            .ok()?;
+        let (writer, guard) = tracing_appender::non_blocking(writer);
+        layers.push(format_layer(format, writer));
+
+        Some(guard)
+    });
+
+    let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+    let _ = FILTER_HANDLE.set(handle);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layers)
+        .init();
+
+    guard
+}
+
+/// Replaces the log filter at runtime, accepts the same directives as `RUST_LOG`.
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(level).map_err(|e| format!("Invalid log level: {e}"))?;
+
+    FILTER_HANDLE
+        .get()
+        .ok_or_else(|| String::from("Logger is not initialized"))?
+        .reload(filter)
+        .map_err(|e| format!("Failed to reload log filter: {e}"))
+}
+
+fn format_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(false);
+
+    match format {
+        LogFormat::Json => layer.json().boxed(),
+        LogFormat::Text => layer.boxed(),
+    }
+}
+
+/// The directory for files kept between sessions, e.g. `~/.local/share/discord-presence-lsp`
+pub fn data_directory() -> Option<PathBuf> {
+    let data_directory = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+
+    Some(data_directory.join(LOG_PREFIX))
+}
+
+fn log_directory() -> Option<PathBuf> {
+    data_directory().map(|directory| directory.join("logs"))
+}
+
+fn file_writer(directory: &Path) -> io::Result<Box<dyn Write + Send>> {
+    let max_files = env::var("DISCORD_PRESENCE_LOG_MAX_FILES")
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .filter(|max| *max > 0)
+        .unwrap_or(DEFAULT_MAX_FILES);
+    let rotation = env::var("DISCORD_PRESENCE_LOG_ROTATION")
+        .map_or(LogRotation::Daily, |rotation| LogRotation::parse(&rotation));
+
+    fs::create_dir_all(directory)?;
+    prune(directory, max_files)?;
+
+    let rotation = match rotation {
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Size(max_bytes) => {
+            return Ok(Box::new(SizeRollingWriter::new(
+                directory, max_bytes, max_files,
+            )?));
+        }
+    };
+
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(LOG_PREFIX)
+        .filename_suffix("log")
+        .max_log_files(max_files)
+        .build(directory)
+        .map_err(io::Error::other)?;
+
+    Ok(Box::new(appender))
+}
+
+/// Deletes the oldest log files so at most `max_files` remain.
+fn prune(directory: &Path, max_files: usize) -> io::Result<()> {
+    let mut files: Vec<(SystemTime, PathBuf)> = fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(LOG_PREFIX))
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, entry.path()))
+        })
+        .collect();
+
+    files.sort_by_key(|(modified, _)| Reverse(*modified));
+
+    for (_, path) in files.into_iter().skip(max_files) {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Writes to `discord-presence-lsp.log`, moving it aside once it would exceed `max_bytes`.
+struct SizeRollingWriter {
+    directory: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRollingWriter {
+    fn new(directory: &Path, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = Self::open(directory)?;
+        let written = file.metadata()?.len();
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn current_path(directory: &Path) -> PathBuf {
+        directory.join(format!("{LOG_PREFIX}.log"))
+    }
+
+    fn open(directory: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::current_path(directory))
+    }
+
+    fn roll(&mut self) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        fs::rename(
+            Self::current_path(&self.directory),
+            self.directory.join(format!("{LOG_PREFIX}.{timestamp}.log")),
+        )?;
+
+        self.file = Self::open(&self.directory)?;
+        self.written = 0;
+
+        prune(&self.directory, self.max_files)
+    }
+}
+
+impl Write for SizeRollingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.roll()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("{LOG_PREFIX}-test-{name}"));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    #[test]
+    fn test_parse_rotation() {
+        assert_eq!(LogRotation::parse("hourly"), LogRotation::Hourly);
+        assert_eq!(LogRotation::parse("size:1024"), LogRotation::Size(1024));
+        assert_eq!(LogRotation::parse("size:lots"), LogRotation::Daily);
+        assert_eq!(LogRotation::parse("daily"), LogRotation::Daily);
+    }
+
+    #[test]
+    fn test_size_rotation_keeps_max_files() {
+        let directory = temp_directory("size");
+        let mut writer = SizeRollingWriter::new(&directory, 8, 2).unwrap();
+
+        for _ in 0..4 {
+            writer.write_all(b"12345678").unwrap();
+            // Rolled files are named by millisecond timestamp
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let files = fs::read_dir(&directory).unwrap().count();
+        assert_eq!(files, 2);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -23,55 +23,184 @@ use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use configuration::Configuration;
-use discord::Discord;
-use git::get_repository_and_remote;
+use configuration::{ActivityOverride, Configuration, PrivacyMode, TimestampMode, UpdateOn};
+use discord::{ActivityFields, ConnectionStatus, CurrentActivity, Discord, Stats};
+use documents::OpenDocuments;
+use events::EventSocket;
+use git::{
+    get_head_path, get_repository_and_remote, get_repository_name, get_status, is_ignored,
+    GitStatus,
+};
+use serde::Deserialize;
+use session::Starts;
+use throttle::Throttle;
 use tokio::sync::{Mutex, MutexGuard};
 use tokio::task::JoinHandle;
 use tokio::time;
-use tower_lsp::jsonrpc::Result;
+use tower_lsp::jsonrpc::{Error, ErrorCode, Result};
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
+use tracing::{debug, error, field, info_span, Instrument, Span};
 use util::Placeholders;
 
+mod clock;
 mod configuration;
 mod discord;
+mod documents;
+mod events;
 mod git;
 mod languages;
+mod lock;
+mod logger;
+mod session;
+mod throttle;
 mod util;
 
+const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shown instead of the workspace name when the repository is considered private
+const PRIVATE_WORKSPACE: &str = "a private repository";
+
+/// Shown instead of `state`/`details` with `privacy.mode = "language_only"`
+const LANGUAGE_ONLY_STATE: &str = "Writing {language:u|code}";
+const LANGUAGE_ONLY_DETAILS: &str = "In Zed";
+
+#[derive(Debug, Deserialize)]
+struct SetLogLevelParams {
+    level: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CollabParams {
+    active: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct EnabledParams {
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct FocusedParams {
+    focused: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ActiveDocumentParams {
+    uri: Url,
+}
+
 #[derive(Debug)]
 struct Document {
     path: PathBuf,
+    shebang: Option<String>, // first line of the text when it starts with `#!`
+    dirty: bool,             // has unsaved changes
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Backend {
     client: Client,
     discord: Arc<Mutex<Discord>>,
     workspace_file_name: Arc<Mutex<String>>,
+    workspace_path: Arc<Mutex<PathBuf>>,
     git_remote_url: Arc<Mutex<Option<String>>>,
+    git_status: Arc<Mutex<GitStatus>>,
+    project_language: Arc<Mutex<Option<String>>>, // most common language of the workspace
     config: Arc<Mutex<Configuration>>,
     idle_timeout: Arc<Mutex<Option<JoinHandle<()>>>>,
+    heartbeat: Arc<Mutex<Option<JoinHandle<()>>>>,
+    lock_watcher: Arc<Mutex<Option<JoinHandle<()>>>>,
+    pending_connect: Arc<Mutex<bool>>, // `connection.lazy` connects with the first document
+    last_language: Arc<Mutex<Option<String>>>, // of the last shown document
+    open_documents: Arc<Mutex<OpenDocuments>>,
+    collab_active: Arc<Mutex<bool>>,
+    enabled: Arc<Mutex<bool>>,
+    focused: Arc<Mutex<bool>>, // reported by the client with `discord-presence/setFocused`
+    dnd_active: Arc<Mutex<bool>>, // the activity was cleared for `dnd` hours
+    idle_count: Arc<Mutex<usize>>, // times gone idle, advances the `idle.variants` rotation
+    starts: Arc<Mutex<Starts>>, // for `timestamp.card_source` and `elapsed_source`
+    events: Arc<Mutex<Option<EventSocket>>>, // only with `events.socket_path`
+    updates: Throttle,
+    span: Span,
+}
+
+/// Returns the percent-decoded path of the url, so every segment matches the file system.
+/// Drive letters lose the leading slash (`/C:/...` becomes `C:/...`) and a host makes it a
+/// UNC path (`//server/share/...`).
+fn url_path(url: &Url) -> PathBuf {
+    let decoded =
+        urlencoding::decode(url.path()).map_or_else(|_| url.path().into(), |p| p.into_owned());
+
+    let path = match url
+        .host_str()
+        .filter(|host| !host.is_empty() && *host != "localhost")
+    {
+        Some(host) => format!("//{host}{decoded}"),
+        None if has_drive_letter(&decoded) => decoded[1..].to_string(),
+        None => decoded,
+    };
+
+    #[cfg(windows)]
+    let path = path.replace('/', "\\");
+
+    PathBuf::from(path)
+}
+
+/// Whether the url path starts with a Windows drive letter, e.g. `/C:/Users`
+fn has_drive_letter(path: &str) -> bool {
+    match path.as_bytes() {
+        [b'/', letter, b':'] => letter.is_ascii_alphabetic(),
+        [b'/', letter, b':', b'/', ..] => letter.is_ascii_alphabetic(),
+        _ => false,
+    }
 }
 
 impl Document {
     fn new(url: Url) -> Self {
-        let url_path = url.path();
-        let path = Path::new(url_path);
-
         Self {
-            path: path.to_owned(),
+            path: url_path(&url),
+            shebang: None,
+            dirty: false,
         }
     }
 
+    fn with_shebang(mut self, shebang: Option<String>) -> Self {
+        self.shebang = shebang;
+        self
+    }
+
+    fn with_dirty(mut self, dirty: bool) -> Self {
+        self.dirty = dirty;
+        self
+    }
+
     fn get_filename(&self) -> String {
-        let filename = self.path.file_name().unwrap().to_str().unwrap();
-        let filename = urlencoding::decode(filename).unwrap();
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Whether the document names a file, directory-like uris with a trailing slash
+    /// and the root don't.
+    fn has_filename(&self) -> bool {
+        !self
+            .path
+            .as_os_str()
+            .to_string_lossy()
+            .ends_with(['/', '\\'])
+            && self.path.file_name().is_some()
+    }
+
+    fn is_inside(&self, workspace_path: &Path, resolve_symlinks: bool) -> bool {
+        if resolve_symlinks {
+            return util::resolve_symlinks(&self.path)
+                .starts_with(util::resolve_symlinks(workspace_path));
+        }
 
-        filename.to_string()
+        self.path.starts_with(workspace_path)
     }
 
     fn get_extension(&self) -> &str {
@@ -84,39 +213,96 @@ impl Document {
 }
 
 impl Backend {
-    fn new(client: Client) -> Self {
+    fn new(client: Client, span: Span) -> Self {
         Self {
             client,
             discord: Arc::new(Mutex::new(Discord::new())),
             workspace_file_name: Arc::new(Mutex::new(String::new())),
+            workspace_path: Arc::new(Mutex::new(PathBuf::new())),
             git_remote_url: Arc::new(Mutex::new(None)),
+            git_status: Arc::new(Mutex::new(GitStatus::default())),
+            project_language: Arc::new(Mutex::new(None)),
             config: Arc::new(Mutex::new(Configuration::new())),
             idle_timeout: Arc::new(Mutex::new(None)),
+            heartbeat: Arc::new(Mutex::new(None)),
+            lock_watcher: Arc::new(Mutex::new(None)),
+            pending_connect: Arc::new(Mutex::new(false)),
+            last_language: Arc::new(Mutex::new(None)),
+            open_documents: Arc::new(Mutex::new(OpenDocuments::default())),
+            collab_active: Arc::new(Mutex::new(false)),
+            enabled: Arc::new(Mutex::new(true)),
+            focused: Arc::new(Mutex::new(true)),
+            dnd_active: Arc::new(Mutex::new(false)),
+            idle_count: Arc::new(Mutex::new(0)),
+            starts: Arc::new(Mutex::new(Starts::default())),
+            events: Arc::new(Mutex::new(None)),
+            updates: Throttle::default(),
+            span,
+        }
+    }
+
+    async fn on_active(&self, url: Url) {
+        let (only_workspace_files, resolve_symlinks) = {
+            let config = self.get_config().await;
+            (config.only_workspace_files, config.resolve_symlinks)
+        };
+
+        if only_workspace_files
+            && !Document::new(url.clone())
+                .is_inside(&self.workspace_path.lock().await, resolve_symlinks)
+        {
+            // Keep the current presence for files outside of the workspace
+            return;
         }
+
+        let doc = {
+            let mut open_documents = self.open_documents.lock().await;
+            open_documents.push(url.clone());
+            open_documents.document(url)
+        };
+        self.on_change(doc).await;
     }
 
     async fn on_change(&self, doc: Document) {
-        self.reset_idle_timeout().await;
+        let (counts, restart) = {
+            let config = self.get_config().await;
+            let language = languages::document_language(&doc, &config);
+            let counts = config.idle.counts(&language, doc.get_extension());
 
-        let (state, details, large_image, large_text, small_image, small_text, git_integration) =
-            self.get_config_values(Some(&doc)).await;
+            let last_language = self.last_language.lock().await.replace(language.clone());
+            let restart = config.timestamp.mode == TimestampMode::Language
+                && last_language.is_some_and(|last| last != language);
 
-        self.get_discord()
+            (counts, restart.then_some(config.timestamp.end_in_secs))
+        };
+        let running = self
+            .idle_timeout
+            .lock()
             .await
-            .change_activity(
-                state,
-                details,
-                large_image,
-                large_text,
-                small_image,
-                small_text,
-                if git_integration {
-                    self.get_git_remote_url().await
-                } else {
-                    None
-                },
-            )
-            .await;
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished());
+
+        // Files that aren't active work only start the timeout, they never postpone it
+        if counts || !running {
+            self.reset_idle_timeout().await;
+        }
+
+        self.connect_pending().await;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.starts.lock().await.switch_file(&doc.path, now);
+
+        if let Some(end_in_secs) = restart {
+            let mut discord = self.get_discord().await;
+            discord.set_start(now);
+            discord.set_end_in(end_in_secs);
+        }
+
+        let fields = self.get_config_values(Some(&doc)).await;
+
+        self.update_activity(fields).await;
     }
 
     async fn reset_idle_timeout(&self) {
@@ -126,58 +312,350 @@ impl Backend {
             handle.abort();
         }
 
-        let discord_clone = Arc::clone(&self.discord);
-        let config_clone = Arc::clone(&self.config);
-        let git_remote_url_clone = Arc::clone(&self.git_remote_url);
+        if !*self.enabled.lock().await {
+            return;
+        }
 
         let timeout_duration = {
-            let config_guard = config_clone.lock().await;
+            let config_guard = self.config.lock().await;
+            if !config_guard.idle.enabled {
+                return;
+            }
+
             Duration::from_secs(config_guard.idle.timeout)
         };
 
-        let handle = tokio::spawn(async move {
-            time::sleep(timeout_duration).await;
+        let backend = self.clone();
+        let handle = tokio::spawn(
+            async move {
+                time::sleep(timeout_duration).await;
+                backend.go_idle().await;
+            }
+            .instrument(self.span.clone()),
+        );
 
-            let config_guard = config_clone.lock().await;
-            let placeholders = Placeholders::new(None, &config_guard, "");
+        *idle_timeout = Some(handle);
+    }
 
-            let discord_guard = discord_clone.lock().await;
+    async fn go_idle(&self) {
+        let elapsed = self.elapsed().await;
+        let status = self.get_discord().await.status().await;
+        let fields = {
+            let config_guard = self.config.lock().await;
+            let git_status = self.git_status.lock().await.clone();
 
-            if config_guard.idle.action == configuration::IdleAction::ClearActivity {
-                discord_guard.clear_activity().await;
-                return;
-            }
+            // Reuse the last document so `{language}` still resolves while idle
+            let last_document = if config_guard.idle.keep_language {
+                self.open_documents.lock().await.active_document()
+            } else {
+                None
+            };
+            let workspace_path = self.workspace_path.lock().await.clone();
+            let last_document =
+                Self::visible_document(&config_guard, &workspace_path, last_document.as_ref());
+            let project_language = self.project_language.lock().await.clone();
+            let placeholders = Placeholders::new(last_document, &config_guard, "", &git_status)
+                .with_elapsed(elapsed)
+                .with_project_language(project_language.as_deref());
 
-            let (state, details, large_image, large_text, small_image, small_text) =
-                Backend::process_fields(
+            if config_guard.idle.action == configuration::IdleAction::ClearActivity
+                || config_guard.privacy.mode == PrivacyMode::Hidden
+            {
+                // Empty fields clear the activity
+                ActivityFields::default()
+            } else {
+                let idle = &config_guard.idle;
+                let variant = {
+                    let mut idle_count = self.idle_count.lock().await;
+                    *idle_count += 1;
+                    idle.variant(*idle_count - 1)
+                };
+                let pick = |field: fn(&ActivityOverride) -> &Option<String>,
+                            regular: &Option<String>| {
+                    variant
+                        .and_then(|variant| field(variant).clone())
+                        .or_else(|| regular.clone())
+                };
+
+                let small_image = if config_guard.status_small_image {
+                    Some(status_small_image(status, true).to_string())
+                } else {
+                    pick(|v| &v.small_image, &idle.small_image)
+                };
+                let mut fields = Backend::process_fields(
+                    &config_guard,
                     &placeholders,
-                    &config_guard.idle.state,
-                    &config_guard.idle.details,
-                    &config_guard.idle.large_image,
-                    &config_guard.idle.large_text,
-                    &config_guard.idle.small_image,
-                    &config_guard.idle.small_text,
+                    &pick(|v| &v.state, &idle.state),
+                    &pick(|v| &v.details, &idle.details),
+                    &pick(|v| &v.large_image, &idle.large_image),
+                    &pick(|v| &v.large_text, &idle.large_text),
+                    &small_image,
+                    &pick(|v| &v.small_text, &idle.small_text),
                 );
 
-            discord_guard
-                .change_activity(
-                    state,
-                    details,
-                    large_image,
-                    large_text,
-                    small_image,
-                    small_text,
-                    if config_guard.git_integration {
-                        let git_remote_url_guard = git_remote_url_clone.lock().await;
-                        git_remote_url_guard.clone()
+                if config_guard.git_integration && config_guard.privacy.mode == PrivacyMode::Full {
+                    let git_remote_url_guard = self.git_remote_url.lock().await;
+                    let url = git_remote_url_guard
+                        .clone()
+                        .filter(|url| !config_guard.git.hides(url));
+                    Self::set_git_button(&mut fields, &config_guard, &placeholders, url);
+                }
+
+                fields.application_id = config_guard.idle.application_id.clone();
+                fields
+            }
+        };
+
+        self.update_activity(fields).await;
+    }
+
+    /// Sends the activity at most once per `min_update_interval_secs`, as Discord rate limits updates.
+    async fn update_activity(&self, fields: ActivityFields) {
+        self.update_activity_at(fields, clock::now()).await;
+    }
+
+    /// Clears the activity once when `now` is in the `dnd` hours and drops the updates after it,
+    /// the next update after the hours end is sent again.
+    async fn update_activity_at(&self, fields: ActivityFields, now: clock::LocalTime) {
+        if !*self.enabled.lock().await {
+            return;
+        }
+
+        let (interval, dnd, card_source) = {
+            let config = self.get_config().await;
+            (
+                Duration::from_secs(config.min_update_interval_secs),
+                config.dnd.active(now),
+                config.timestamp.card_source,
+            )
+        };
+
+        let was_dnd = std::mem::replace(&mut *self.dnd_active.lock().await, dnd);
+        if dnd && was_dnd {
+            return;
+        }
+        // Empty fields clear the activity
+        let mut fields = if dnd {
+            ActivityFields::default()
+        } else {
+            fields
+        };
+        if let Some(source) = card_source {
+            fields.start = Some(self.starts.lock().await.get(source));
+        }
+
+        let discord = Arc::clone(&self.discord);
+        let events = self
+            .events
+            .lock()
+            .await
+            .as_ref()
+            .map(EventSocket::publisher);
+
+        self.updates
+            .run(
+                interval,
+                async move {
+                    let discord = discord.lock().await;
+                    discord.change_activity(fields).await;
+
+                    if let Some(events) = events {
+                        events.publish(&discord.current_activity().await);
+                    }
+                }
+                .instrument(self.span.clone()),
+            )
+            .await;
+    }
+
+    /// Goes idle right away when the screen gets locked and restores the presence once it's unlocked.
+    async fn start_lock_watcher(&self) {
+        let backend = self.clone();
+        let handle = tokio::spawn(
+            async move {
+                let mut interval = time::interval(LOCK_POLL_INTERVAL);
+                let mut was_locked = false;
+
+                loop {
+                    interval.tick().await;
+
+                    let Ok(Some(locked)) =
+                        tokio::task::spawn_blocking(lock::is_screen_locked).await
+                    else {
+                        debug!("Screen lock detection is not supported, stopping the watcher");
+                        return;
+                    };
+
+                    if locked == was_locked {
+                        continue;
+                    }
+                    was_locked = locked;
+
+                    if locked {
+                        backend.go_idle_now().await;
                     } else {
-                        None
-                    },
-                )
-                .await;
-        });
+                        backend.restore_active().await;
+                    }
+                }
+            }
+            .instrument(self.span.clone()),
+        );
 
-        *idle_timeout = Some(handle);
+        *self.lock_watcher.lock().await = Some(handle);
+    }
+
+    /// Publishes the presence to the readers of the socket after every update.
+    async fn start_events(&self, path: &str) {
+        match EventSocket::listen(path) {
+            Ok(socket) => *self.events.lock().await = Some(socket),
+            Err(e) => error!("Failed to listen for events readers at {path}: {e}"),
+        }
+    }
+
+    /// Goes idle without waiting for the timeout, e.g. when the screen gets locked.
+    async fn go_idle_now(&self) {
+        if let Some(handle) = self.idle_timeout.lock().await.take() {
+            handle.abort();
+        }
+        self.go_idle().await;
+    }
+
+    /// Shows the active document again, which also restarts the idle timeout.
+    async fn restore_active(&self) {
+        let active = self.open_documents.lock().await.active_document();
+        if let Some(doc) = active {
+            self.on_change(doc).await;
+        }
+    }
+
+    /// Connects when `connection.lazy` deferred it in `initialize`, Discord is usually
+    /// running by the time a document is edited.
+    async fn connect_pending(&self) {
+        if !std::mem::take(&mut *self.pending_connect.lock().await) {
+            return;
+        }
+
+        let heartbeat_secs = self.get_config().await.connection.heartbeat_secs;
+        if let Err(e) = self.get_discord().await.connect().await {
+            error!("Failed to connect to Discord: {e}");
+        }
+        self.start_heartbeat(heartbeat_secs).await;
+    }
+
+    async fn start_heartbeat(&self, interval_secs: u64) {
+        if interval_secs == 0 {
+            return;
+        }
+
+        let discord_clone = Arc::clone(&self.discord);
+        let handle = tokio::spawn(
+            async move {
+                let mut interval = time::interval(Duration::from_secs(interval_secs));
+                // The first tick completes immediately
+                interval.tick().await;
+
+                loop {
+                    interval.tick().await;
+                    discord_clone.lock().await.heartbeat().await;
+                }
+            }
+            .instrument(self.span.clone()),
+        );
+
+        *self.heartbeat.lock().await = Some(handle);
+    }
+
+    async fn languages(&self) -> Result<Vec<String>> {
+        Ok(languages::get_languages(&*self.get_config().await))
+    }
+
+    async fn current_activity(&self) -> Result<CurrentActivity> {
+        Ok(self.get_discord().await.current_activity().await)
+    }
+
+    async fn stats(&self) -> Result<Stats> {
+        Ok(self.get_discord().await.stats())
+    }
+
+    /// The configuration after merging the options, the config file, profiles and the environment.
+    async fn resolved_config(&self) -> Result<serde_json::Value> {
+        serde_json::to_value(&*self.get_config().await).map_err(|e| {
+            error!("Failed to serialize the configuration: {e}");
+            Error::internal_error()
+        })
+    }
+
+    async fn set_log_level(&self, params: SetLogLevelParams) -> Result<()> {
+        logger::set_level(&params.level).map_err(Error::invalid_params)
+    }
+
+    async fn set_collab(&self, params: CollabParams) {
+        *self.collab_active.lock().await = params.active;
+
+        let active = self.open_documents.lock().await.active_document();
+        if let Some(doc) = active {
+            self.on_change(doc).await;
+        }
+    }
+
+    /// Pauses the presence for the rest of the session, e.g. while screen recording.
+    async fn set_enabled(&self, params: EnabledParams) {
+        *self.enabled.lock().await = params.enabled;
+
+        if !params.enabled {
+            if let Some(handle) = self.idle_timeout.lock().await.take() {
+                handle.abort();
+            }
+            self.updates.cancel().await;
+            self.get_discord().await.suspend().await;
+            return;
+        }
+
+        self.get_discord().await.resume().await;
+
+        let active = self.open_documents.lock().await.active_document();
+        if let Some(doc) = active {
+            self.on_change(doc).await;
+        }
+    }
+
+    /// Goes idle when Zed's window loses focus with `idle.on_focus_loss`, and comes back
+    /// once it's focused again.
+    async fn set_focused(&self, params: FocusedParams) {
+        if *self.focused.lock().await == params.focused {
+            return;
+        }
+        *self.focused.lock().await = params.focused;
+
+        let on_focus_loss = {
+            let config = self.get_config().await;
+            config.idle.enabled && config.idle.on_focus_loss
+        };
+        if !on_focus_loss || !*self.enabled.lock().await {
+            return;
+        }
+
+        if params.focused {
+            self.restore_active().await;
+        } else {
+            self.go_idle_now().await;
+        }
+    }
+
+    /// LSP has no notification for switching editors, so this lets the client report
+    /// documents that are only being read.
+    async fn did_change_active_document(&self, params: ActiveDocumentParams) {
+        self.on_active(params.uri).await;
+    }
+
+    /// Seeds the document shown on activation, as tabs restored with the workspace don't always
+    /// get a `did_open`. It's ignored once a document was synced, as that one is more recent.
+    async fn set_document(&self, params: ActiveDocumentParams) {
+        if self.open_documents.lock().await.active().is_some() {
+            return;
+        }
+
+        self.on_active(params.uri).await;
     }
 
     async fn get_workspace_file_name(&self) -> MutexGuard<'_, String> {
@@ -190,16 +668,187 @@ impl Backend {
         guard.clone()
     }
 
-    async fn get_config(&self) -> MutexGuard<Configuration> {
+    async fn get_config(&self) -> MutexGuard<'_, Configuration> {
         return self.config.lock().await;
     }
 
-    async fn get_discord(&self) -> MutexGuard<Discord> {
+    async fn get_discord(&self) -> MutexGuard<'_, Discord> {
         return self.discord.lock().await;
     }
 
-    #[allow(clippy::type_complexity)]
+    /// Time since the session started, for `{elapsed}`, or since `timestamp.elapsed_source`
+    async fn elapsed(&self) -> Duration {
+        let source = self.get_config().await.timestamp.elapsed_source();
+        let start = match source {
+            Some(source) => self.starts.lock().await.get(source),
+            None => self.get_discord().await.start_timestamp(),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        now.saturating_sub(start)
+    }
+
+    /// Watches `HEAD` so `{git_branch}` follows checkouts, worktrees keep it in the main repository.
+    async fn watch_git_head(&self) {
+        let repository_path = self
+            .workspace_path
+            .lock()
+            .await
+            .to_string_lossy()
+            .to_string();
+        let Ok(Some(head_path)) =
+            tokio::task::spawn_blocking(move || get_head_path(&repository_path)).await
+        else {
+            return;
+        };
+
+        let mut watchers = vec![FileSystemWatcher {
+            glob_pattern: GlobPattern::String(String::from("**/.git/HEAD")),
+            kind: None,
+        }];
+        if !head_path.ends_with(".git/HEAD") {
+            watchers.push(FileSystemWatcher {
+                glob_pattern: GlobPattern::String(head_path.to_string_lossy().to_string()),
+                kind: None,
+            });
+        }
+
+        let registration = Registration {
+            id: String::from("discord-presence/git-head"),
+            method: String::from("workspace/didChangeWatchedFiles"),
+            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
+                watchers,
+            })
+            .ok(),
+        };
+
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            debug!("Failed to watch git HEAD: {e}");
+        }
+    }
+
+    async fn refresh_git_status(&self) {
+        let repository_path = self
+            .workspace_path
+            .lock()
+            .await
+            .to_string_lossy()
+            .to_string();
+        let Ok(git_status) =
+            tokio::task::spawn_blocking(move || get_status(&repository_path)).await
+        else {
+            return;
+        };
+
+        {
+            let mut current = self.git_status.lock().await;
+            if *current == git_status {
+                return;
+            }
+
+            self.span.record(
+                "git_branch",
+                git_status.branch.as_deref().unwrap_or_default(),
+            );
+            *current = git_status;
+        }
+
+        let active = self.open_documents.lock().await.active_document();
+        if let Some(doc) = active {
+            self.on_change(doc).await;
+        }
+    }
+
+    /// Adds the repository button, its label may contain placeholders
+    fn set_git_button(
+        fields: &mut ActivityFields,
+        config: &Configuration,
+        placeholders: &Placeholders,
+        url: Option<String>,
+    ) {
+        fields.git_button_label = url
+            .as_ref()
+            .map(|_| placeholders.replace(&config.git.button_label));
+        fields.git_remote_url = url;
+    }
+
+    /// Hides directories, documents whose language is excluded by the rules and those that are
+    /// ignored by git, the activity is shown as if no file was open instead.
+    fn visible_document<'a>(
+        config: &Configuration,
+        workspace_path: &Path,
+        doc: Option<&'a Document>,
+    ) -> Option<&'a Document> {
+        doc.filter(|doc| doc.has_filename())
+            .filter(|doc| {
+                config
+                    .rules
+                    .allows_language(&languages::document_language(doc, config))
+            })
+            .filter(|doc| {
+                let relative = util::relative_path(&doc.path, workspace_path);
+                config.rules.allows_document(relative.as_deref())
+            })
+            .filter(|doc| {
+                !config.rules.respect_gitignore
+                    || !workspace_path
+                        .to_str()
+                        .is_some_and(|path| is_ignored(path, &doc.path))
+            })
+    }
+
+    /// Applies `privacy.mode` to the activity, the placeholders already leave out any names.
+    fn apply_privacy(
+        config: &Configuration,
+        placeholders: &Placeholders,
+        fields: ActivityFields,
+    ) -> ActivityFields {
+        match config.privacy.mode {
+            PrivacyMode::Full => fields,
+            PrivacyMode::LanguageOnly => ActivityFields {
+                state: Some(placeholders.replace(LANGUAGE_ONLY_STATE)),
+                details: Some(placeholders.replace(LANGUAGE_ONLY_DETAILS)),
+                git_remote_url: None,
+                git_button_label: None,
+                ..fields
+            },
+            // Empty fields clear the activity
+            PrivacyMode::Hidden => ActivityFields::default(),
+        }
+    }
+
+    /// Resolves the regular activity, where each template is taken from the first override that
+    /// sets it.
+    fn process_overrides(
+        config: &Configuration,
+        placeholders: &Placeholders,
+        has_git: bool,
+        overrides: &[&ActivityOverride],
+    ) -> ActivityFields {
+        let pick = |field: fn(&ActivityOverride) -> &Option<String>, regular: &Option<String>| {
+            overrides
+                .iter()
+                .find_map(|activity| field(activity).clone())
+                .or_else(|| regular.clone())
+        };
+
+        Self::process_fields(
+            config,
+            placeholders,
+            &pick(|a| &a.state, config.state_for(has_git)),
+            &pick(|a| &a.details, config.details_for(has_git)),
+            &pick(|a| &a.large_image, &config.large_image),
+            &pick(|a| &a.large_text, &config.large_text),
+            &pick(|a| &a.small_image, &config.small_image),
+            &pick(|a| &a.small_text, &config.small_text),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn process_fields(
+        config: &Configuration,
         placeholders: &Placeholders,
         state: &Option<String>,
         details: &Option<String>,
@@ -207,101 +856,312 @@ impl Backend {
         large_text: &Option<String>,
         small_image: &Option<String>,
         small_text: &Option<String>,
-    ) -> (
-        Option<String>,
-        Option<String>,
-        Option<String>,
-        Option<String>,
-        Option<String>,
-        Option<String>,
-    ) {
-        let state = state.as_ref().map(|s| placeholders.replace(s));
-        let details = details.as_ref().map(|d| placeholders.replace(d));
-        let large_image = large_image.as_ref().map(|img| placeholders.replace(img));
-        let large_text = large_text.as_ref().map(|text| placeholders.replace(text));
-        let small_image = small_image.as_ref().map(|img| placeholders.replace(img));
-        let small_text = small_text.as_ref().map(|text| placeholders.replace(text));
-
-        (
-            state,
-            details,
-            large_image,
-            large_text,
-            small_image,
-            small_text,
-        )
+    ) -> ActivityFields {
+        let fallback_image = config
+            .fallback_image
+            .clone()
+            .filter(|_| placeholders.language() == Some(config.default_language.as_str()));
+        let large_image = if fallback_image.is_some() {
+            &fallback_image
+        } else {
+            large_image
+        };
+
+        let mut fields = ActivityFields {
+            state: state.as_ref().map(|s| placeholders.replace(s)),
+            details: details.as_ref().map(|d| placeholders.replace(d)),
+            large_image: large_image.as_ref().map(|img| placeholders.replace(img)),
+            large_text: large_text.as_ref().map(|text| placeholders.replace(text)),
+            small_image: small_image.as_ref().map(|img| placeholders.replace(img)),
+            small_text: small_text.as_ref().map(|text| placeholders.replace(text)),
+            git_remote_url: None,
+            git_button_label: None,
+            party_size: config.party.size(),
+            application_id: None,
+            start: None,
+        };
+
+        if config.use_asset_keys {
+            for image in [&mut fields.large_image, &mut fields.small_image] {
+                *image = image.take().map(|image| asset_key(config, image));
+            }
+        }
+
+        if config.swap_images {
+            std::mem::swap(&mut fields.large_image, &mut fields.small_image);
+            std::mem::swap(&mut fields.large_text, &mut fields.small_text);
+        }
+
+        if !config.show_small_image {
+            fields.small_image = None;
+            fields.small_text = None;
+        }
+
+        if !config.show_large_image {
+            // Discord only renders the small image on top of the large one,
+            // so the small image takes its place
+            fields.large_image = fields.small_image.take();
+            fields.large_text = fields.small_text.take();
+        }
+
+        fields
     }
 
-    #[allow(clippy::type_complexity)]
-    async fn get_config_values(
-        &self,
-        doc: Option<&Document>,
-    ) -> (
-        Option<String>,
-        Option<String>,
-        Option<String>,
-        Option<String>,
-        Option<String>,
-        Option<String>,
-        bool,
-    ) {
+    async fn get_config_values(&self, doc: Option<&Document>) -> ActivityFields {
+        let elapsed = self.elapsed().await;
+        let status = self.get_discord().await.status().await;
         let config = self.get_config().await;
+        let git_remote_url = self.get_git_remote_url().await;
+        let private = git_remote_url
+            .as_deref()
+            .is_some_and(|url| config.git.hides(url));
+
         let workspace = self.get_workspace_file_name().await;
-        let placeholders = Placeholders::new(doc, &config, workspace.deref());
+        let workspace = if private {
+            PRIVATE_WORKSPACE
+        } else {
+            workspace.deref()
+        };
+        let git_status = self.git_status.lock().await.clone();
+        let has_git = git_remote_url.is_some() || git_status.branch.is_some();
+        let workspace_path = self.workspace_path.lock().await.clone();
+        let doc = Self::visible_document(&config, &workspace_path, doc);
+        let project_language = self.project_language.lock().await.clone();
+        let placeholders = Placeholders::new(doc, &config, workspace, &git_status)
+            .with_elapsed(elapsed)
+            .with_project_language(project_language.as_deref());
 
-        let (state, details, large_image, large_text, small_image, small_text) =
-            Self::process_fields(
-                &placeholders,
-                &config.state,
-                &config.details,
-                &config.large_image,
-                &config.large_text,
-                &config.small_image,
-                &config.small_text,
-            );
+        // Takes precedence over the small image of every other override
+        let status_image = config.status_small_image.then(|| ActivityOverride {
+            small_image: Some(status_small_image(status, false).to_string()),
+            ..Default::default()
+        });
+        let dirty_image = config
+            .dirty_small_image
+            .clone()
+            .filter(|_| doc.is_some_and(|doc| doc.dirty))
+            .map(|image| ActivityOverride {
+                small_image: Some(image),
+                ..Default::default()
+            });
+        let collab = (*self.collab_active.lock().await).then_some(&config.collab);
+        let extension = doc.and_then(|doc| config.extension_override(&doc.get_filename()));
+        let overrides: Vec<&ActivityOverride> = status_image
+            .iter()
+            .chain(&dirty_image)
+            .chain(collab)
+            .chain(extension)
+            .chain(config.schedule.active(clock::now()))
+            .collect();
 
-        (
-            state,
-            details,
-            large_image,
-            large_text,
-            small_image,
-            small_text,
-            config.git_integration,
-        )
+        let mut fields = Self::process_overrides(&config, &placeholders, has_git, &overrides);
+
+        if config.git_integration && !private {
+            Self::set_git_button(&mut fields, &config, &placeholders, git_remote_url);
+        }
+
+        Self::apply_privacy(&config, &placeholders, fields)
     }
 }
 
-#[tower_lsp::async_trait]
-impl LanguageServer for Backend {
-    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
-        // Set workspace name
-        let root_uri = params.root_uri.expect("Failed to get root uri");
-        let workspace_path = Path::new(root_uri.path());
-        self.workspace_file_name.lock().await.push_str(
-            workspace_path
-                .file_name()
-                .expect("Failed to get workspace file name")
-                .to_str()
-                .expect("Failed to convert workspace file name &OsStr to &str"),
-        );
+/// Turns an image under `base_icons_url` into the key of an asset uploaded to the Discord
+/// application for `use_asset_keys`, e.g. `rust` for `{base_icons_url}/rust.png`. Other URLs
+/// are kept.
+fn asset_key(config: &Configuration, image: String) -> String {
+    let Some(name) = image
+        .strip_prefix(config.base_icons_url.as_str())
+        .and_then(|name| name.strip_prefix('/'))
+    else {
+        return image;
+    };
 
-        let mut git_remote_url = self.git_remote_url.lock().await;
-        *git_remote_url = get_repository_and_remote(workspace_path.to_str().unwrap());
+    name.rsplit_once('.')
+        .map_or(name, |(stem, _)| stem)
+        .to_lowercase()
+}
 
-        let mut config = self.config.lock().await;
-        config.set(params.initialization_options);
+/// The small image template for `status_small_image`, a reconnect shows over being idle.
+fn status_small_image(status: ConnectionStatus, idle: bool) -> &'static str {
+    match status {
+        ConnectionStatus::Reconnecting => "{base_icons_url}/reconnecting.png",
+        _ if idle => "{base_icons_url}/idle.png",
+        _ => "{base_icons_url}/zed.png",
+    }
+}
 
-        let mut discord = self.get_discord().await;
-        discord.create_client(config.application_id.to_string());
+/// Returns the last path segment, ignoring trailing separators, or `workspace` for a root path.
+fn get_workspace_name(path: &str) -> String {
+    let trimmed = path.trim_end_matches(['/', '\\']);
+    let name = Path::new(trimmed)
+        .file_name()
+        .and_then(OsStr::to_str)
+        .unwrap_or_else(|| trimmed.trim_start_matches(['/', '\\']));
 
-        if config.rules.suitable(
-            workspace_path
-                .to_str()
-                .expect("Failed to transform workspace path to str"),
-        ) {
-            // Connect discord client
-            discord.connect().await;
+    if name.is_empty() {
+        String::from("workspace")
+    } else {
+        name.to_string()
+    }
+}
+
+/// Drops trailing separators so `/project/` and `/project` are the same workspace, the root
+/// and drive roots like `C:/` keep theirs.
+fn trim_trailing_separators(path: PathBuf) -> PathBuf {
+    let full = path.to_string_lossy();
+    let trimmed = full.trim_end_matches(['/', '\\']);
+
+    if trimmed.len() == full.len() || trimmed.is_empty() || trimmed.ends_with(':') {
+        return path;
+    }
+
+    PathBuf::from(trimmed)
+}
+
+/// A file opened on its own stands for its directory, a path that doesn't exist is kept as it is.
+fn workspace_dir(path: PathBuf) -> PathBuf {
+    if path.is_file() {
+        path.parent().map_or(path.clone(), Path::to_path_buf)
+    } else {
+        path
+    }
+}
+
+/// Uses the repository name of the remote with `workspace_name.from_git`, falling back to the directory name.
+fn workspace_name(config: &Configuration, path: &str, remote_url: Option<&str>) -> String {
+    remote_url
+        .filter(|_| config.workspace_name.from_git)
+        .and_then(get_repository_name)
+        .map_or_else(|| get_workspace_name(path), str::to_string)
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        // Without a folder, the workspace is named `workspace` and has no repository
+        let root_uri = params.root_uri.or_else(|| {
+            params
+                .workspace_folders
+                .and_then(|folders| folders.into_iter().next())
+                .map(|folder| folder.uri)
+        });
+        let workspace_path = root_uri.as_ref().map_or_else(PathBuf::new, |uri| {
+            workspace_dir(trim_trailing_separators(url_path(uri)))
+        });
+        *self.workspace_path.lock().await = workspace_path.clone();
+
+        let repository_path = workspace_path.to_str().unwrap_or_default().to_owned();
+        let (remote_url, git_status, project_language) = tokio::task::spawn_blocking(move || {
+            if repository_path.is_empty() {
+                return Default::default();
+            }
+
+            (
+                get_repository_and_remote(&repository_path),
+                get_status(&repository_path),
+                languages::project_language(Path::new(&repository_path)),
+            )
+        })
+        .await
+        .unwrap_or_default();
+        *self.project_language.lock().await = project_language;
+
+        self.span.record(
+            "git_branch",
+            git_status.branch.as_deref().unwrap_or_default(),
+        );
+        *self.git_status.lock().await = git_status;
+
+        let mut git_remote_url = self.git_remote_url.lock().await;
+        *git_remote_url = remote_url;
+
+        let mut config = self.config.lock().await;
+        config.set_workspace_path(&workspace_path.to_string_lossy());
+        if let Err(e) = config.set(params.initialization_options) {
+            error!("Invalid configuration: {e}");
+            return Err(Error {
+                code: ErrorCode::InvalidParams,
+                message: "Invalid discord presence configuration".into(),
+                data: Some(serde_json::Value::String(e.to_string())),
+            });
+        }
+
+        // Set workspace name
+        let workspace = workspace_name(
+            &config,
+            &workspace_path.to_string_lossy(),
+            git_remote_url.as_deref(),
+        );
+        self.span.record("workspace", workspace.as_str());
+        *self.workspace_file_name.lock().await = workspace;
+
+        let mut discord = self.get_discord().await;
+        discord.create_client(config.application_id.to_string());
+        let workspace_key = workspace_path.to_string_lossy();
+        let opened = discord.start_timestamp();
+        if let Some(start) = session::restore_start(&config.timestamp, &workspace_key) {
+            discord.set_start(start);
+        }
+        session::save_start(&config.timestamp, &workspace_key, discord.start_timestamp());
+        discord.set_end_in(config.timestamp.end_in_secs);
+
+        // The start `mode` keeps is one of the sources, the other one starts now unless it's saved
+        let mode_start = discord.start_timestamp();
+        let workspace_start = if config.timestamp.mode == TimestampMode::Workspace {
+            mode_start
+        } else if config.timestamp.needs_workspace_start() {
+            let start = session::restore_workspace_start(&config.timestamp, &workspace_key)
+                .unwrap_or(opened);
+            session::save_workspace_start(&config.timestamp, &workspace_key, start);
+            start
+        } else {
+            opened
+        };
+        *self.starts.lock().await = Starts {
+            session: if config.timestamp.mode == TimestampMode::Workspace {
+                opened
+            } else {
+                mode_start
+            },
+            workspace: workspace_start,
+            file: opened,
+            file_path: None,
+        };
+
+        // Restored tabs are opened in a burst, only the document open at the end is shown
+        self.updates
+            .hold(Duration::from_millis(config.startup_grace_ms))
+            .await;
+
+        let rules_path = if config.resolve_symlinks {
+            util::resolve_symlinks(&workspace_path)
+        } else {
+            workspace_path.clone()
+        };
+
+        if config.rules.suitable(
+            rules_path
+                .to_str()
+                .expect("Failed to transform workspace path to str"),
+            git_remote_url.as_deref(),
+        ) {
+            if config.connection.lazy {
+                *self.pending_connect.lock().await = true;
+            } else {
+                // Connect discord client
+                // The heartbeat keeps retrying when Discord isn't running yet
+                if let Err(e) = discord.connect().await {
+                    error!("Failed to connect to Discord: {e}");
+                }
+                self.start_heartbeat(config.connection.heartbeat_secs).await;
+            }
+
+            if config.idle.enabled && config.idle.on_screen_lock {
+                self.start_lock_watcher().await;
+            }
+
+            if let Some(path) = &config.events.socket_path {
+                self.start_events(&util::expand_home(path)).await;
+            }
         } else {
             // Exit LSP
             exit(0);
@@ -313,8 +1173,13 @@ impl LanguageServer for Backend {
                 version: Some(env!("CARGO_PKG_VERSION").into()),
             }),
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::INCREMENTAL,
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                        ..Default::default()
+                    },
                 )),
                 ..Default::default()
             },
@@ -328,31 +1193,1227 @@ impl LanguageServer for Backend {
                 "Discord Presence LSP server intiailized!",
             )
             .await;
+
+        self.watch_git_head().await;
     }
 
     async fn shutdown(&self) -> Result<()> {
-        self.get_discord().await.kill().await;
+        for task in [&self.heartbeat, &self.lock_watcher, &self.idle_timeout] {
+            if let Some(handle) = task.lock().await.take() {
+                handle.abort();
+            }
+        }
+
+        self.updates.cancel().await;
+
+        let workspace_path = self.workspace_path.lock().await.clone();
+        let config = self.get_config().await;
+        let discord = self.get_discord().await;
+        // Marks when the session ended for the grace window
+        session::save_start(
+            &config.timestamp,
+            &workspace_path.to_string_lossy(),
+            discord.start_timestamp(),
+        );
+        if let Some(events) = self.events.lock().await.take() {
+            events.close();
+        }
+        if config.timestamp.needs_workspace_start() {
+            session::save_workspace_start(
+                &config.timestamp,
+                &workspace_path.to_string_lossy(),
+                self.starts.lock().await.workspace,
+            );
+        }
+        discord.kill().await;
 
         Ok(())
     }
 
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        let (end_in_secs, idle_enabled) = {
+            let mut config = self.get_config().await;
+            if let Err(e) = config.set(Some(params.settings)) {
+                // Keeps the previous configuration
+                self.client
+                    .log_message(MessageType::ERROR, format!("Invalid configuration: {e}"))
+                    .await;
+                return;
+            }
+
+            let path = self
+                .workspace_path
+                .lock()
+                .await
+                .to_string_lossy()
+                .into_owned();
+            let remote_url = self.get_git_remote_url().await;
+            *self.workspace_file_name.lock().await =
+                workspace_name(&config, &path, remote_url.as_deref());
+
+            (config.timestamp.end_in_secs, config.idle.enabled)
+        };
+        self.get_discord().await.set_end_in(end_in_secs);
+
+        if !idle_enabled {
+            // A scheduled timeout would still fire without an active document to reset it
+            if let Some(handle) = self.idle_timeout.lock().await.take() {
+                handle.abort();
+            }
+        }
+
+        let active = self.open_documents.lock().await.active_document();
+        if let Some(doc) = active {
+            self.on_change(doc).await;
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        if params
+            .changes
+            .iter()
+            .any(|change| change.uri.path().ends_with("/HEAD"))
+        {
+            self.refresh_git_status().await;
+        }
+    }
+
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        self.on_change(Document::new(params.text_document.uri))
-            .await;
+        self.open_documents
+            .lock()
+            .await
+            .set_shebang(&params.text_document.uri, &params.text_document.text);
+        self.on_active(params.text_document.uri).await;
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        self.on_change(Document::new(params.text_document.uri))
-            .await;
+        self.open_documents
+            .lock()
+            .await
+            .set_dirty(&params.text_document.uri, true);
+
+        if self.get_config().await.update_on.accepts(UpdateOn::Change) {
+            self.on_active(params.text_document.uri).await;
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        self.open_documents
+            .lock()
+            .await
+            .set_dirty(&params.text_document.uri, false);
+
+        if self.get_config().await.update_on.accepts(UpdateOn::Save) {
+            self.on_active(params.text_document.uri).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let next = {
+            let mut open_documents = self.open_documents.lock().await;
+            if !open_documents.close(&params.text_document.uri) {
+                return;
+            }
+
+            open_documents.active_document()
+        };
+
+        match next {
+            Some(doc) => self.on_change(doc).await,
+            None => self.update_activity(ActivityFields::default()).await,
+        }
+    }
+}
+
+/// Connects to Discord once with the configuration from the environment, outside of Zed.
+async fn check() -> i32 {
+    let mut config = Configuration::new();
+    if let Err(e) = config.set(None) {
+        println!("Invalid configuration: {e}");
+        return 1;
+    }
+
+    let mut discord = Discord::new();
+    discord.create_client(config.application_id.clone());
+
+    match discord.connect().await {
+        Ok(()) => {
+            println!(
+                "Connected to Discord (application id {})",
+                config.application_id
+            );
+            discord.kill().await;
+            0
+        }
+        Err(e) => {
+            println!("Failed to connect to Discord: {e}");
+            1
+        }
     }
 }
 
 #[tokio::main]
 async fn main() {
+    match std::env::args().nth(1).as_deref() {
+        Some("--version") => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Some("--check") => exit(check().await),
+        _ => {}
+    }
+
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::new(Backend::new);
+    let _guard = logger::init();
+
+    // Fields are recorded during initialize, so logs of multiple Zed windows can be told apart
+    let span = info_span!(
+        "discord_presence",
+        workspace = field::Empty,
+        git_branch = field::Empty
+    );
+
+    let backend_span = span.clone();
+    let (service, socket) = LspService::build(|client| Backend::new(client, backend_span))
+        .custom_method("discord-presence/languages", Backend::languages)
+        .custom_method(
+            "discord-presence/currentActivity",
+            Backend::current_activity,
+        )
+        .custom_method("discord-presence/stats", Backend::stats)
+        .custom_method("discord-presence/config", Backend::resolved_config)
+        .custom_method("discord-presence/setLogLevel", Backend::set_log_level)
+        .custom_method("discord-presence/collab", Backend::set_collab)
+        .custom_method("discord-presence/setEnabled", Backend::set_enabled)
+        .custom_method("discord-presence/setDocument", Backend::set_document)
+        .custom_method("discord-presence/setFocused", Backend::set_focused)
+        .custom_method(
+            "discord-presence/didChangeActiveDocument",
+            Backend::did_change_active_document,
+        )
+        .finish();
+
+    Server::new(stdin, stdout, socket)
+        .serve(service)
+        .instrument(span)
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use serde_json::json;
+    use tower_lsp::ClientSocket;
+
+    use super::*;
+
+    /// A backend with a client that is never connected and no update interval, so every
+    /// update is sent right away. `configure` changes the configuration on top of that.
+    async fn test_backend(
+        configure: impl FnOnce(&mut Configuration),
+    ) -> (LspService<Backend>, ClientSocket) {
+        let (service, socket) = LspService::new(|client| Backend::new(client, Span::none()));
+        let backend = service.inner();
+        backend
+            .get_discord()
+            .await
+            .create_client(Configuration::new().application_id);
+
+        let mut config = backend.get_config().await;
+        config.min_update_interval_secs = 0;
+        configure(&mut config);
+        drop(config);
+
+        (service, socket)
+    }
+
+    /// The activity last sent, or kept while the client couldn't send it.
+    async fn sent_activity(backend: &Backend) -> Option<ActivityFields> {
+        backend
+            .get_discord()
+            .await
+            .current_activity()
+            .await
+            .activity
+    }
+
+    fn process_config_fields(config: &Configuration, doc: Option<&Document>) -> ActivityFields {
+        let git_status = GitStatus::default();
+        let doc = Backend::visible_document(config, Path::new(""), doc);
+        let placeholders = Placeholders::new(doc, config, "workspace", &git_status);
+
+        Backend::process_fields(
+            config,
+            &placeholders,
+            &config.state,
+            &config.details,
+            &config.large_image,
+            &config.large_text,
+            &config.small_image,
+            &config.small_text,
+        )
+    }
+
+    #[test]
+    fn test_get_workspace_name() {
+        assert_eq!(get_workspace_name("/home/user/project"), "project");
+        assert_eq!(get_workspace_name("/home/user/project/"), "project");
+        assert_eq!(get_workspace_name("/"), "workspace");
+        assert_eq!(get_workspace_name(""), "workspace");
+        assert_eq!(get_workspace_name("/C:/"), "C:");
+        assert_eq!(get_workspace_name("C:\\"), "C:");
+    }
+
+    #[test]
+    fn test_document_inside_workspace() {
+        let workspace = Path::new("/home/user/project");
+        let document = Document::new(Url::parse("file:///home/user/project/src/main.rs").unwrap());
+
+        assert!(document.is_inside(workspace, false));
+    }
+
+    #[test]
+    fn test_percent_encoded_segments() {
+        let url = Url::parse("file:///home/user/My%20Project/src%23/main%20file.rs").unwrap();
+        let document = Document::new(url);
+
+        assert_eq!(document.get_filename(), "main file.rs");
+        assert!(document.is_inside(Path::new("/home/user/My Project"), false));
+
+        let root = Url::parse("file:///home/user/My%20Project/").unwrap();
+        assert_eq!(
+            get_workspace_name(&url_path(&root).to_string_lossy()),
+            "My Project"
+        );
+    }
+
+    #[test]
+    fn test_windows_drive_letter_uri() {
+        let url = Url::parse("file:///C:/Users/me/My%20Project/main.rs").unwrap();
+        let document = Document::new(url);
+
+        assert_eq!(document.path, Path::new("C:/Users/me/My Project/main.rs"));
+        assert_eq!(document.get_filename(), "main.rs");
+        assert_eq!(document.get_extension(), "rs");
+
+        let root = url_path(&Url::parse("file:///C:/Users/me/My%20Project").unwrap());
+        assert!(document.is_inside(&root, false));
+        assert_eq!(get_workspace_name(&root.to_string_lossy()), "My Project");
+        assert_eq!(
+            url_path(&Url::parse("file:///C:").unwrap()),
+            Path::new("C:")
+        );
+        assert_eq!(
+            url_path(&Url::parse("file:///Cargo:/x").unwrap()),
+            Path::new("/Cargo:/x")
+        );
+    }
+
+    #[test]
+    fn test_unc_uri() {
+        let url = Url::parse("file://server/share/project/src/main.rs").unwrap();
+        let document = Document::new(url);
+
+        assert_eq!(
+            document.path,
+            Path::new("//server/share/project/src/main.rs")
+        );
+        assert_eq!(document.get_filename(), "main.rs");
+
+        let root = url_path(&Url::parse("file://server/share/project").unwrap());
+        assert!(document.is_inside(&root, false));
+        assert!(!document.is_inside(Path::new("/share/project"), false));
+    }
+
+    #[test]
+    fn test_document_outside_workspace() {
+        let workspace = Path::new("/home/user/project");
+        let sibling = Document::new(Url::parse("file:///home/user/project-lib/lib.rs").unwrap());
+        let external = Document::new(Url::parse("file:///usr/lib/rust/src/vec.rs").unwrap());
+
+        assert!(!sibling.is_inside(workspace, false));
+        assert!(!external.is_inside(workspace, false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_document_inside_symlinked_workspace() {
+        let dir = std::env::temp_dir().join("discord-presence-lsp-test-symlinked-workspace");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("project/src")).unwrap();
+        std::fs::write(dir.join("project/src/main.rs"), "").unwrap();
+        std::os::unix::fs::symlink(dir.join("project"), dir.join("link")).unwrap();
+
+        let url = Url::from_file_path(dir.join("link/src/main.rs")).unwrap();
+        let document = Document::new(url);
+        let workspace = dir.join("project");
+
+        assert!(!document.is_inside(&workspace, false));
+        assert!(document.is_inside(&workspace, true));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_swap_images() {
+        let mut config = Configuration::new();
+        config.large_image = Some(String::from("large.png"));
+        config.large_text = Some(String::from("Large"));
+        config.small_image = Some(String::from("small.png"));
+        config.small_text = Some(String::from("Small"));
+        config.swap_images = true;
+
+        let fields = process_config_fields(&config, None);
+
+        assert_eq!(fields.large_image.as_deref(), Some("small.png"));
+        assert_eq!(fields.large_text.as_deref(), Some("Small"));
+        assert_eq!(fields.small_image.as_deref(), Some("large.png"));
+        assert_eq!(fields.small_text.as_deref(), Some("Large"));
+    }
+
+    #[test]
+    fn test_small_fields_resolve_like_state() {
+        let template = "{language:u} in {workspace} ({filename:lo})";
+        let mut config = Configuration::new();
+        config.state = Some(String::from(template));
+        config.details = Some(String::from(template));
+        config.small_text = Some(String::from(template));
+        config.small_image = Some(String::from("{base_icons_url}/{language}.png"));
+
+        let rust = Document::new(Url::parse("file:///home/user/Main.rs").unwrap());
+        let fields = process_config_fields(&config, Some(&rust));
+        assert_eq!(fields.state.as_deref(), Some("Rust in workspace (main.rs)"));
+        assert_eq!(fields.small_text, fields.state);
+        assert_eq!(fields.small_text, fields.details);
+        assert_eq!(fields.small_image, fields.large_image);
+
+        let fields = process_config_fields(&config, None);
+        assert_eq!(fields.small_text, fields.state);
+    }
+
+    #[test]
+    fn test_fallback_image_for_unknown_language() {
+        let mut config = Configuration::new();
+        config.base_icons_url = String::from("https://example.com");
+        config.fallback_image = Some(String::from("{base_icons_url}/unknown.png"));
+
+        let unknown = Document::new(Url::parse("file:///home/user/file.unknownext").unwrap());
+        let fields = process_config_fields(&config, Some(&unknown));
+        assert_eq!(
+            fields.large_image.as_deref(),
+            Some("https://example.com/unknown.png")
+        );
+
+        let rust = Document::new(Url::parse("file:///home/user/main.rs").unwrap());
+        let fields = process_config_fields(&config, Some(&rust));
+        assert_eq!(
+            fields.large_image.as_deref(),
+            Some("https://example.com/rust.png")
+        );
+    }
+
+    #[test]
+    fn test_asset_keys() {
+        let mut config = Configuration::new();
+        config.use_asset_keys = true;
+
+        let rust = Document::new(Url::parse("file:///home/user/main.rs").unwrap());
+        let fields = process_config_fields(&config, Some(&rust));
+        assert_eq!(fields.large_image.as_deref(), Some("rust"));
+        assert_eq!(fields.small_image.as_deref(), Some("zed"));
+
+        // Images that aren't icons are kept
+        config.small_image = Some(String::from("https://example.com/Avatar.png"));
+        let fields = process_config_fields(&config, Some(&rust));
+        assert_eq!(
+            fields.small_image.as_deref(),
+            Some("https://example.com/Avatar.png")
+        );
+
+        config.use_asset_keys = false;
+        let fields = process_config_fields(&config, Some(&rust));
+        assert!(fields
+            .large_image
+            .is_some_and(|image| image.ends_with("/rust.png")));
+    }
+
+    #[test]
+    fn test_blacklisted_language_hides_the_file() {
+        let mut config = Configuration::new();
+        config.state = Some(String::from("Working on {filename}"));
+        config.rules.languages.push(String::from("markdown"));
+
+        let notes = Document::new(Url::parse("file:///home/user/notes.md").unwrap());
+        let fields = process_config_fields(&config, Some(&notes));
+        assert_eq!(fields.state.as_deref(), Some("Working on filename"));
+
+        let rust = Document::new(Url::parse("file:///home/user/main.rs").unwrap());
+        let fields = process_config_fields(&config, Some(&rust));
+        assert_eq!(fields.state.as_deref(), Some("Working on main.rs"));
+    }
+
+    #[test]
+    fn test_directory_uri_shows_no_file() {
+        let mut config = Configuration::new();
+        config.state = Some(String::from("Working on {filename}"));
+
+        for url in ["file:///home/user/project/src/", "file:///"] {
+            let directory = Document::new(Url::parse(url).unwrap());
+            assert!(!directory.has_filename());
+
+            let fields = process_config_fields(&config, Some(&directory));
+            assert_eq!(fields.state.as_deref(), Some("Working on filename"));
+            assert_eq!(
+                fields.large_text.as_deref(),
+                process_config_fields(&config, None).large_text.as_deref()
+            );
+        }
+    }
 
-    Server::new(stdin, stdout, socket).serve(service).await;
+    #[test]
+    fn test_schedule_overrides() {
+        let mut config = Configuration::new();
+        config.schedule.weekend = Some(ActivityOverride {
+            state: Some(String::from("Coding on a weekend 😎")),
+            ..Default::default()
+        });
+
+        let git_status = GitStatus::default();
+        let rust = Document::new(Url::parse("file:///home/user/main.rs").unwrap());
+        let placeholders = Placeholders::new(Some(&rust), &config, "project", &git_status);
+        let resolve = |time| {
+            let overrides: Vec<&ActivityOverride> =
+                config.schedule.active(time).into_iter().collect();
+            Backend::process_overrides(&config, &placeholders, false, &overrides)
+        };
+
+        let saturday = clock::LocalTime {
+            weekday: 6,
+            minutes: 10 * 60,
+        };
+        let fields = resolve(saturday);
+        assert_eq!(fields.state.as_deref(), Some("Coding on a weekend 😎"));
+        assert_eq!(fields.details.as_deref(), Some("In project"));
+
+        let monday = clock::LocalTime {
+            weekday: 1,
+            minutes: 10 * 60,
+        };
+        assert_eq!(resolve(monday).state.as_deref(), Some("Working on main.rs"));
+
+        // The collab activity takes precedence over the schedule
+        let overrides = [&config.collab, config.schedule.active(saturday).unwrap()];
+        let fields = Backend::process_overrides(&config, &placeholders, false, &overrides);
+        assert_eq!(fields.state.as_deref(), Some("Coding on a weekend 😎"));
+        assert_eq!(
+            fields.details.as_deref(),
+            Some("Pair programming in project")
+        );
+    }
+
+    #[test]
+    fn test_git_button_label() {
+        let mut config = Configuration::new();
+        config.git.button_label = String::from("{workspace:u} on GitHub");
+
+        let git_status = GitStatus::default();
+        let placeholders = Placeholders::new(None, &config, "workspace", &git_status);
+        let mut fields = ActivityFields::default();
+
+        Backend::set_git_button(&mut fields, &config, &placeholders, None);
+        assert_eq!(fields.git_button_label, None);
+
+        let url = String::from("https://github.com/xhyrom/zed-discord-presence");
+        Backend::set_git_button(&mut fields, &config, &placeholders, Some(url));
+        assert_eq!(
+            fields.git_button_label.as_deref(),
+            Some("Workspace on GitHub")
+        );
+    }
+
+    #[test]
+    fn test_git_and_no_git_details() {
+        let mut config = Configuration::new();
+        config.git_details = Some(String::from("In {workspace} ({git_branch})"));
+
+        let with_git = GitStatus {
+            branch: Some(String::from("main")),
+            ..Default::default()
+        };
+        let placeholders = Placeholders::new(None, &config, "project", &with_git);
+        let details = config.details_for(true).as_ref().unwrap();
+        assert_eq!(placeholders.replace(details), "In project (main)");
+
+        let without_git = GitStatus::default();
+        let placeholders = Placeholders::new(None, &config, "project", &without_git);
+        let details = config.details_for(false).as_ref().unwrap();
+        assert_eq!(placeholders.replace(details), "In project");
+
+        assert_eq!(config.state_for(true), &config.state);
+
+        config.no_git_details = Some(String::from("Somewhere in {workspace}"));
+        assert_eq!(config.details_for(false), &config.no_git_details);
+    }
+
+    #[test]
+    fn test_gitignored_document_is_hidden() {
+        let workspace = std::env::temp_dir().join("discord-presence-lsp-test-gitignored");
+        let _ = std::fs::remove_dir_all(&workspace);
+        git2::Repository::init(&workspace).unwrap();
+        std::fs::write(workspace.join(".gitignore"), "dist/\n").unwrap();
+
+        let mut config = Configuration::new();
+        let bundle = Document::new(Url::from_file_path(workspace.join("dist/bundle.js")).unwrap());
+        let source = Document::new(Url::from_file_path(workspace.join("src/main.rs")).unwrap());
+
+        assert!(Backend::visible_document(&config, &workspace, Some(&bundle)).is_some());
+
+        config.rules.respect_gitignore = true;
+        assert!(Backend::visible_document(&config, &workspace, Some(&bundle)).is_none());
+        assert!(Backend::visible_document(&config, &workspace, Some(&source)).is_some());
+
+        std::fs::remove_dir_all(workspace).unwrap();
+    }
+
+    #[test]
+    fn test_privacy_modes() {
+        let mut config = Configuration::new();
+        config.large_text = Some(String::from("{filename} in {workspace}"));
+        config.small_text = Some(String::from("On {git_branch|no branch}"));
+
+        let git_status = GitStatus {
+            branch: Some(String::from("secret-feature")),
+            ..Default::default()
+        };
+        let doc = Document::new(Url::parse("file:///home/user/secret/main.rs").unwrap());
+        let url = Some(String::from("https://github.com/user/secret"));
+
+        let resolve = |config: &Configuration| {
+            let placeholders = Placeholders::new(Some(&doc), config, "secret", &git_status);
+            let mut fields = Backend::process_fields(
+                config,
+                &placeholders,
+                &config.state,
+                &config.details,
+                &config.large_image,
+                &config.large_text,
+                &config.small_image,
+                &config.small_text,
+            );
+            Backend::set_git_button(&mut fields, config, &placeholders, url.clone());
+
+            Backend::apply_privacy(config, &placeholders, fields)
+        };
+
+        let fields = resolve(&config);
+        assert_eq!(fields.state.as_deref(), Some("Working on main.rs"));
+        assert_eq!(fields.details.as_deref(), Some("In secret"));
+        assert_eq!(fields.large_text.as_deref(), Some("main.rs in secret"));
+        assert_eq!(fields.small_text.as_deref(), Some("On secret-feature"));
+        assert!(fields.git_remote_url.is_some());
+
+        config.privacy.mode = PrivacyMode::LanguageOnly;
+        let fields = resolve(&config);
+        assert_eq!(fields.state.as_deref(), Some("Writing Rust"));
+        assert_eq!(fields.details.as_deref(), Some("In Zed"));
+        assert_eq!(fields.large_text.as_deref(), Some("filename in "));
+        assert_eq!(fields.small_text.as_deref(), Some("On no branch"));
+        assert_eq!(
+            fields.large_image.as_deref(),
+            Some(format!("{}/rust.png", config.base_icons_url).as_str())
+        );
+        assert_eq!(fields.git_remote_url, None);
+        assert_eq!(fields.git_button_label, None);
+
+        config.privacy.mode = PrivacyMode::Hidden;
+        assert!(resolve(&config).is_empty());
+    }
+
+    #[test]
+    fn test_status_small_image() {
+        for (status, idle, image) in [
+            (ConnectionStatus::Connected, false, "zed.png"),
+            (ConnectionStatus::Disconnected, false, "zed.png"),
+            (ConnectionStatus::Connected, true, "idle.png"),
+            (ConnectionStatus::Reconnecting, false, "reconnecting.png"),
+            (ConnectionStatus::Reconnecting, true, "reconnecting.png"),
+        ] {
+            assert_eq!(
+                status_small_image(status, idle),
+                format!("{{base_icons_url}}/{image}")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_small_image_is_opt_in() {
+        let (service, _socket) = test_backend(|config| {
+            config.small_image = Some(String::from("custom.png"));
+        })
+        .await;
+        let backend = service.inner();
+
+        let fields = backend.get_config_values(None).await;
+        assert_eq!(fields.small_image.as_deref(), Some("custom.png"));
+
+        backend.get_config().await.status_small_image = true;
+        let fields = backend.get_config_values(None).await;
+        assert_eq!(
+            fields.small_image,
+            Some(format!("{}/zed.png", Configuration::new().base_icons_url))
+        );
+    }
+
+    #[test]
+    fn test_root_uri_only() {
+        for (root_uri, path, name) in [
+            (
+                "file:///home/user/project/",
+                "/home/user/project",
+                "project",
+            ),
+            (
+                "file:///home/user/project//",
+                "/home/user/project",
+                "project",
+            ),
+            ("file:///", "/", "workspace"),
+        ] {
+            let workspace_path = trim_trailing_separators(url_path(&Url::parse(root_uri).unwrap()));
+            assert_eq!(workspace_path, Path::new(path));
+            assert_eq!(get_workspace_name(workspace_path.to_str().unwrap()), name);
+        }
+
+        assert_eq!(
+            trim_trailing_separators(PathBuf::from("C:/")),
+            Path::new("C:/")
+        );
+    }
+
+    #[test]
+    fn test_workspace_dir() {
+        let root = env::temp_dir().join("discord-presence-lsp-test-workspace-dir");
+        fs::create_dir_all(&root).unwrap();
+        let file = root.join("notes.md");
+        fs::write(&file, "").unwrap();
+        let missing = root.join("not-created-yet");
+
+        assert_eq!(workspace_dir(root.clone()), root);
+        assert_eq!(workspace_dir(file), root);
+        assert_eq!(workspace_dir(missing.clone()), missing);
+        assert_eq!(
+            get_workspace_name(&workspace_dir(missing).to_string_lossy()),
+            "not-created-yet"
+        );
+        assert_eq!(workspace_dir(PathBuf::new()), PathBuf::new());
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_resolved_config() {
+        // Set directly, as the environment of other tests would be merged by `set`
+        let (service, _socket) = test_backend(|config| {
+            config.state = Some(String::from("Hacking on {filename}"));
+            config.idle.timeout = 60;
+            config.idle.action = configuration::IdleAction::ClearActivity;
+            config.schedule.hours.push(configuration::Hours {
+                from: 22 * 60,
+                to: 6 * 60,
+                activity: ActivityOverride {
+                    details: Some(String::from("Late")),
+                    ..Default::default()
+                },
+            });
+            config.profiles.push((
+                String::from("/home/user/work/**"),
+                json!({ "state": "Working" }),
+            ));
+        })
+        .await;
+        let backend = service.inner();
+
+        let config = backend.resolved_config().await.unwrap();
+        assert_eq!(config["state"], "Hacking on {filename}");
+        assert_eq!(config["idle"]["timeout"], 60);
+        assert_eq!(config["idle"]["action"], "clear_activity");
+        assert_eq!(config["idle"]["details"], "In Zed");
+        assert_eq!(config["schedule"]["hours"][0]["from"], "22:00");
+        assert_eq!(config["schedule"]["hours"][0]["details"], "Late");
+        assert!(
+            config["profiles"]
+                .as_object()
+                .unwrap()
+                .values()
+                .next()
+                .unwrap()["state"]
+                == "Working"
+        );
+        assert!(config.get("workspace_path").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_focus_loss() {
+        let (service, _socket) = test_backend(|_| {}).await;
+        let backend = service.inner();
+
+        let state = || async {
+            sent_activity(backend)
+                .await
+                .and_then(|activity| activity.state)
+        };
+        let focused = |focused| FocusedParams { focused };
+
+        let url = Url::parse("file:///home/user/project/main.rs").unwrap();
+        backend.on_active(url).await;
+        assert_eq!(state().await.as_deref(), Some("Working on main.rs"));
+
+        // Disabled by default
+        backend.set_focused(focused(false)).await;
+        assert_eq!(state().await.as_deref(), Some("Working on main.rs"));
+        backend.set_focused(focused(true)).await;
+
+        backend.get_config().await.idle.on_focus_loss = true;
+        backend.set_focused(focused(false)).await;
+        assert_eq!(state().await.as_deref(), Some("Idling"));
+        assert!(backend.idle_timeout.lock().await.is_none());
+
+        backend.set_focused(focused(true)).await;
+        assert_eq!(state().await.as_deref(), Some("Working on main.rs"));
+        assert!(backend.idle_timeout.lock().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_idle_variants_round_robin() {
+        let (service, _socket) = test_backend(|config| {
+            config.idle.variants = ["Getting coffee", "Stretching"]
+                .map(|state| ActivityOverride {
+                    state: Some(String::from(state)),
+                    ..Default::default()
+                })
+                .into();
+        })
+        .await;
+        let backend = service.inner();
+
+        let idle_activity = || async {
+            backend.go_idle().await;
+            sent_activity(backend).await.unwrap()
+        };
+
+        let first = idle_activity().await;
+        assert_eq!(first.state.as_deref(), Some("Getting coffee"));
+        // Unset fields fall back to the idle ones
+        assert_eq!(first.details.as_deref(), Some("In Zed"));
+        let second = idle_activity().await;
+        assert_eq!(second.state.as_deref(), Some("Stretching"));
+        let third = idle_activity().await;
+        assert_eq!(third.state.as_deref(), Some("Getting coffee"));
+
+        backend.get_config().await.idle.variants.clear();
+        let regular = idle_activity().await;
+        assert_eq!(regular.state.as_deref(), Some("Idling"));
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_sources() {
+        let (service, _socket) = test_backend(|config| {
+            config.state = Some(String::from("{elapsed}"));
+            config.timestamp.card_source = Some(configuration::TimestampSource::Session);
+            config.timestamp.elapsed_source = Some(configuration::TimestampSource::File);
+        })
+        .await;
+        let backend = service.inner();
+
+        let url = Url::parse("file:///home/user/project/main.rs").unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let session = now - Duration::from_secs(2 * 3600);
+        let file = now - Duration::from_secs(5 * 60);
+        *backend.starts.lock().await = Starts {
+            session,
+            workspace: session,
+            file,
+            file_path: Some(url_path(&url)),
+        };
+
+        let activity = || async { sent_activity(backend).await.unwrap() };
+
+        backend.on_active(url).await;
+        let current = activity().await;
+        assert_eq!(current.start, Some(session));
+        assert_eq!(current.state.as_deref(), Some("5m"));
+
+        // Switching files restarts only the file start
+        let other = Url::parse("file:///home/user/project/lib.rs").unwrap();
+        backend.on_active(other).await;
+        let current = activity().await;
+        assert_eq!(current.start, Some(session));
+        assert_eq!(current.state.as_deref(), Some("0s"));
+
+        // Both follow `mode` without sources
+        backend.get_config().await.timestamp = configuration::Timestamp::default();
+        backend
+            .update_activity(backend.get_config_values(None).await)
+            .await;
+        assert_eq!(activity().await.start, None);
+
+        backend.updates.cancel().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_events_after_document_change() {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+        use tokio::net::UnixStream;
+
+        let (service, _socket) = test_backend(|_| {}).await;
+        let backend = service.inner();
+
+        let path = env::temp_dir().join("discord-presence-lsp-test-backend-events.sock");
+        let path = path.to_str().unwrap();
+        backend.start_events(path).await;
+        let stream = UnixStream::connect(path).await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+
+        let url = Url::parse("file:///home/user/project/main.rs").unwrap();
+        backend.on_active(url).await;
+
+        let line = time::timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(event["activity"]["state"], "Working on main.rs");
+        assert!(event["status"].is_string());
+
+        backend.updates.cancel().await;
+        backend.events.lock().await.take().unwrap().close();
+    }
+
+    #[tokio::test]
+    async fn test_dnd_hours() {
+        let (service, _socket) = test_backend(|config| {
+            config.dnd.hours = vec![configuration::DndHours {
+                from: 18 * 60,
+                to: 9 * 60,
+                days: Vec::new(),
+            }];
+        })
+        .await;
+        let backend = service.inner();
+
+        let state = || async {
+            sent_activity(backend)
+                .await
+                .and_then(|activity| activity.state)
+        };
+        let fields = |state: &str| ActivityFields {
+            state: Some(state.to_string()),
+            ..Default::default()
+        };
+        let at = |hours: u16| clock::LocalTime {
+            weekday: 3,
+            minutes: hours * 60,
+        };
+
+        backend.update_activity_at(fields("Working"), at(17)).await;
+        assert_eq!(state().await.as_deref(), Some("Working"));
+
+        backend.update_activity_at(fields("Evening"), at(19)).await;
+        assert_eq!(state().await, None);
+        assert!(*backend.dnd_active.lock().await);
+
+        backend.update_activity_at(fields("Night"), at(23)).await;
+        assert_eq!(state().await, None);
+
+        backend.update_activity_at(fields("Morning"), at(9)).await;
+        assert_eq!(state().await.as_deref(), Some("Morning"));
+        assert!(!*backend.dnd_active.lock().await);
+    }
+
+    #[test]
+    fn test_extension_overrides() {
+        let mut config = Configuration::new();
+        config.extensions = vec![
+            (
+                String::from("env"),
+                ActivityOverride {
+                    state: Some(String::from("Editing secrets")),
+                    small_image: Some(String::from("{base_icons_url}/lock.png")),
+                    ..Default::default()
+                },
+            ),
+            (
+                String::from("md"),
+                ActivityOverride {
+                    details: Some(String::from("Writing docs")),
+                    ..Default::default()
+                },
+            ),
+        ];
+        let git_status = GitStatus::default();
+        let night = ActivityOverride {
+            state: Some(String::from("Working late on {filename}")),
+            ..Default::default()
+        };
+
+        for (file, state, details) in [
+            ("prod.ENV", "Editing secrets", "In workspace"),
+            (".env", "Editing secrets", "In workspace"),
+            ("README.md", "Working late on README.md", "Writing docs"),
+            ("main.rs", "Working late on main.rs", "In workspace"),
+        ] {
+            let doc = Document::new(Url::parse(&format!("file:///project/{file}")).unwrap());
+            let placeholders = Placeholders::new(Some(&doc), &config, "workspace", &git_status);
+            let extension = config.extension_override(&doc.get_filename());
+            let overrides: Vec<&ActivityOverride> = extension.into_iter().chain([&night]).collect();
+
+            let fields = Backend::process_overrides(&config, &placeholders, false, &overrides);
+            assert_eq!(fields.state.as_deref(), Some(state), "{file}");
+            assert_eq!(fields.details.as_deref(), Some(details), "{file}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dirty_indicator() {
+        let (service, _socket) = test_backend(|config| {
+            config.state = Some(String::from("Working on {filename}{dirty}"));
+            config.dirty_small_image = Some(String::from("{base_icons_url}/dirty.png"));
+        })
+        .await;
+        let backend = service.inner();
+        let uri = Url::parse("file:///home/user/project/main.rs").unwrap();
+
+        let activity = || async {
+            let activity = sent_activity(backend).await.unwrap();
+            (activity.state.unwrap(), activity.small_image.unwrap())
+        };
+
+        backend
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    String::from("rust"),
+                    1,
+                    String::new(),
+                ),
+            })
+            .await;
+        let (state, small_image) = activity().await;
+        assert_eq!(state, "Working on main.rs");
+        assert!(small_image.ends_with("/zed.png"));
+
+        backend
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 2),
+                content_changes: Vec::new(),
+            })
+            .await;
+        let (state, small_image) = activity().await;
+        assert_eq!(state, "Working on main.rs*");
+        assert!(small_image.ends_with("/dirty.png"));
+
+        backend
+            .did_save(DidSaveTextDocumentParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+                text: None,
+            })
+            .await;
+        let (state, small_image) = activity().await;
+        assert_eq!(state, "Working on main.rs");
+        assert!(small_image.ends_with("/zed.png"));
+
+        backend.updates.cancel().await;
+    }
+
+    #[tokio::test]
+    async fn test_update_on_save() {
+        let (service, _socket) = test_backend(|config| config.update_on = UpdateOn::Save).await;
+        let backend = service.inner();
+        let uri = Url::parse("file:///home/user/project/main.rs").unwrap();
+
+        backend
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 1),
+                content_changes: Vec::new(),
+            })
+            .await;
+        assert!(backend
+            .open_documents
+            .lock()
+            .await
+            .active_document()
+            .is_none());
+        assert!(backend.idle_timeout.lock().await.is_none());
+
+        backend
+            .did_save(DidSaveTextDocumentParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+                text: None,
+            })
+            .await;
+        let active = backend.open_documents.lock().await.active_document();
+        assert_eq!(
+            active.map(|doc| doc.get_filename()).as_deref(),
+            Some("main.rs")
+        );
+        assert!(backend.idle_timeout.lock().await.is_some());
+
+        backend.updates.cancel().await;
+    }
+
+    #[tokio::test]
+    async fn test_lazy_connection() {
+        let _guard = configuration::ENV_LOCK.lock().await;
+        let (service, _socket) = test_backend(|_| {}).await;
+        let backend = service.inner();
+        let root = env::temp_dir().join("discord-presence-lsp-test-lazy");
+        fs::create_dir_all(&root).unwrap();
+
+        backend
+            .initialize(InitializeParams {
+                root_uri: Some(Url::from_directory_path(&root).unwrap()),
+                initialization_options: Some(json!({ "connection": { "lazy": true } })),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        // Nothing was attempted, a failed connection would have been recorded
+        assert!(*backend.pending_connect.lock().await);
+        assert!(backend.heartbeat.lock().await.is_none());
+        assert!(backend.get_discord().await.stats().last_error.is_none());
+
+        // Closing before the first document must not touch the never connected client
+        assert!(backend.shutdown().await.is_ok());
+
+        let document = Document::new(Url::from_file_path(root.join("main.rs")).unwrap());
+        backend.on_change(document).await;
+        assert!(!*backend.pending_connect.lock().await);
+        assert!(backend.heartbeat.lock().await.is_some());
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_idle_disabled() {
+        let (service, _socket) = test_backend(|_| {}).await;
+        let backend = service.inner();
+
+        backend.reset_idle_timeout().await;
+        assert!(backend.idle_timeout.lock().await.is_some());
+
+        backend.get_config().await.idle.enabled = false;
+        backend.reset_idle_timeout().await;
+        assert!(backend.idle_timeout.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_idle_active_languages() {
+        let (service, _socket) = test_backend(|config| {
+            config.idle.active_languages = vec![String::from("rust")];
+        })
+        .await;
+        let backend = service.inner();
+
+        let notes = || Document::new(Url::parse("file:///home/user/notes.md").unwrap());
+        let main = || Document::new(Url::parse("file:///home/user/main.rs").unwrap());
+
+        // Stands in for a running timeout, it finishes once the sender is dropped
+        let marker = || {
+            let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+            (
+                tx,
+                tokio::spawn(async move {
+                    rx.await.ok();
+                }),
+            )
+        };
+        let is_marker = |tx, handle: JoinHandle<()>| async move {
+            drop(tx);
+            matches!(
+                time::timeout(Duration::from_secs(1), handle).await,
+                Ok(Ok(()))
+            )
+        };
+
+        backend.on_change(notes()).await;
+        assert!(backend.idle_timeout.lock().await.is_some());
+
+        let (tx, handle) = marker();
+        *backend.idle_timeout.lock().await = Some(handle);
+        backend.on_change(notes()).await;
+        let handle = backend.idle_timeout.lock().await.take().unwrap();
+        assert!(is_marker(tx, handle).await);
+
+        let (tx, handle) = marker();
+        *backend.idle_timeout.lock().await = Some(handle);
+        backend.on_change(main()).await;
+        let handle = backend.idle_timeout.lock().await.take().unwrap();
+        assert!(!is_marker(tx, handle).await);
+
+        backend.updates.cancel().await;
+    }
+
+    #[tokio::test]
+    async fn test_switching_files_keeps_the_start() {
+        let (service, _socket) = test_backend(|_| {}).await;
+        let backend = service.inner();
+        let start = backend.get_discord().await.start_timestamp();
+
+        for file in ["main.rs", "lib.rs"] {
+            let url = Url::parse(&format!("file:///home/user/project/{file}")).unwrap();
+            backend.on_active(url).await;
+        }
+
+        assert_eq!(backend.get_discord().await.start_timestamp(), start);
+        backend.updates.cancel().await;
+    }
+
+    #[tokio::test]
+    async fn test_language_timestamp_mode() {
+        let (service, _socket) = test_backend(|config| {
+            config.timestamp.mode = TimestampMode::Language;
+        })
+        .await;
+        let backend = service.inner();
+        let start = Duration::from_secs(1_000);
+        backend.get_discord().await.set_start(start);
+
+        for file in ["main.rs", "lib.rs"] {
+            let url = Url::parse(&format!("file:///home/user/project/{file}")).unwrap();
+            backend.on_active(url).await;
+        }
+        assert_eq!(backend.get_discord().await.start_timestamp(), start);
+
+        let url = Url::parse("file:///home/user/project/main.go").unwrap();
+        backend.on_active(url).await;
+        assert!(backend.get_discord().await.start_timestamp() > start);
+
+        backend.updates.cancel().await;
+    }
+
+    #[test]
+    fn test_workspace_name_from_git() {
+        let mut config = Configuration::new();
+        let remote = Some("https://github.com/xhyrom/zed-discord-presence");
+
+        assert_eq!(
+            workspace_name(&config, "/tmp/checkout-1234", remote),
+            "checkout-1234"
+        );
+
+        config.workspace_name.from_git = true;
+        assert_eq!(
+            workspace_name(&config, "/tmp/checkout-1234", remote),
+            "zed-discord-presence"
+        );
+        assert_eq!(workspace_name(&config, "/home/user/src", None), "src");
+    }
 }
@@ -0,0 +1,291 @@
+/*
+ * This file is part of discord-presence. Extension for Zed that adds support for Discord Rich Presence using LSP.
+ *
+ * Copyright (c) 2024 Steinhübl
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::configuration::{Timestamp, TimestampMode, TimestampSource};
+use crate::logger;
+
+/// Start of a workspace and when it was last saved, as the file is shared by every workspace
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct WorkspaceStart {
+    start: u64,
+    saved: u64,
+}
+
+/// Keeps the session start so the elapsed time survives restarting Zed
+fn session_file() -> Option<PathBuf> {
+    logger::data_directory().map(|directory| directory.join("session"))
+}
+
+/// Keeps the start of every workspace for `timestamp.mode = "workspace"`
+fn workspaces_file() -> Option<PathBuf> {
+    logger::data_directory().map(|directory| directory.join("workspaces.json"))
+}
+
+/// Returns the saved start for `timestamp.mode` if the previous session ended less than
+/// `grace_secs` ago, `None` when the start isn't persisted.
+pub fn restore_start(timestamp: &Timestamp, workspace: &str) -> Option<Duration> {
+    let grace = Duration::from_secs(timestamp.grace_secs);
+
+    match timestamp.mode {
+        TimestampMode::Workspace => {
+            restore_workspace_from(&workspaces_file()?, workspace, grace, now())
+        }
+        TimestampMode::Session | TimestampMode::Language if timestamp.persist => {
+            restore_from(&session_file()?, grace)
+        }
+        TimestampMode::Session | TimestampMode::Language => None,
+    }
+}
+
+/// Saves the start for `timestamp.mode`, nothing is saved when it isn't persisted.
+pub fn save_start(timestamp: &Timestamp, workspace: &str, start: Duration) {
+    let result = match timestamp.mode {
+        TimestampMode::Workspace => workspaces_file().map(|path| {
+            let grace = Duration::from_secs(timestamp.grace_secs);
+            save_workspace_to(&path, workspace, start, grace, now())
+        }),
+        TimestampMode::Session | TimestampMode::Language if timestamp.persist => {
+            session_file().map(|path| save_to(&path, start))
+        }
+        TimestampMode::Session | TimestampMode::Language => None,
+    };
+
+    if let Some(Err(e)) = result {
+        debug!("Failed to save the session start: {e}");
+    }
+}
+
+/// Returns the saved start of the workspace whatever `timestamp.mode` is, for a
+/// `timestamp.card_source` or `elapsed_source` of `workspace`.
+pub fn restore_workspace_start(timestamp: &Timestamp, workspace: &str) -> Option<Duration> {
+    let grace = Duration::from_secs(timestamp.grace_secs);
+    restore_workspace_from(&workspaces_file()?, workspace, grace, now())
+}
+
+/// Saves the start of the workspace whatever `timestamp.mode` is.
+pub fn save_workspace_start(timestamp: &Timestamp, workspace: &str, start: Duration) {
+    let grace = Duration::from_secs(timestamp.grace_secs);
+    let result =
+        workspaces_file().map(|path| save_workspace_to(&path, workspace, start, grace, now()));
+
+    if let Some(Err(e)) = result {
+        debug!("Failed to save the workspace start: {e}");
+    }
+}
+
+/// The starts the card timestamp and `{elapsed}` can be taken from.
+#[derive(Debug, Clone, Default)]
+pub struct Starts {
+    pub session: Duration,
+    pub workspace: Duration,
+    pub file: Duration,
+    pub file_path: Option<PathBuf>, // of the file `file` is the start of
+}
+
+impl Starts {
+    pub fn get(&self, source: TimestampSource) -> Duration {
+        match source {
+            TimestampSource::Session => self.session,
+            TimestampSource::Workspace => self.workspace,
+            TimestampSource::File => self.file,
+        }
+    }
+
+    /// Restarts the file start when `path` isn't the file it was started for.
+    pub fn switch_file(&mut self, path: &Path, now: Duration) {
+        if self.file_path.as_deref() != Some(path) {
+            self.file = now;
+            self.file_path = Some(path.to_path_buf());
+        }
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn restore_from(path: &Path, grace: Duration) -> Option<Duration> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    if SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default()
+        > grace
+    {
+        return None;
+    }
+
+    // A corrupt file starts a fresh session
+    let secs = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+fn save_to(path: &Path, start: Duration) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, start.as_secs().to_string())
+}
+
+/// A corrupt file is treated like an empty one
+fn read_workspaces(path: &Path) -> BTreeMap<String, WorkspaceStart> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn restore_workspace_from(
+    path: &Path,
+    workspace: &str,
+    grace: Duration,
+    now: Duration,
+) -> Option<Duration> {
+    let entry = read_workspaces(path).get(workspace).copied()?;
+    if now.as_secs().saturating_sub(entry.saved) > grace.as_secs() {
+        return None;
+    }
+
+    Some(Duration::from_secs(entry.start))
+}
+
+/// Saves the start of the workspace, dropping the ones that are past `grace` anyway.
+fn save_workspace_to(
+    path: &Path,
+    workspace: &str,
+    start: Duration,
+    grace: Duration,
+    now: Duration,
+) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut workspaces = read_workspaces(path);
+    workspaces.retain(|_, entry| now.as_secs().saturating_sub(entry.saved) <= grace.as_secs());
+    workspaces.insert(
+        workspace.to_string(),
+        WorkspaceStart {
+            start: start.as_secs(),
+            saved: now.as_secs(),
+        },
+    );
+
+    fs::write(path, serde_json::to_string_pretty(&workspaces)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("discord-presence-lsp-test-session-{name}"));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_restore_start() {
+        let path = temp_file("restore");
+        let start = Duration::from_secs(1_700_000_000);
+
+        assert_eq!(restore_from(&path, Duration::from_secs(60)), None);
+
+        save_to(&path, start).unwrap();
+        assert_eq!(restore_from(&path, Duration::from_secs(60)), Some(start));
+        assert_eq!(restore_from(&path, Duration::ZERO), None);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_workspace_starts() {
+        let path = temp_file("workspaces");
+        let grace = Duration::from_secs(600);
+        let now = Duration::from_secs(1_700_001_000);
+        let (start_a, start_b) = (Duration::from_secs(1_700_000_000), now);
+
+        save_workspace_to(&path, "/home/user/a", start_a, grace, now).unwrap();
+        save_workspace_to(&path, "/home/user/b", start_b, grace, now).unwrap();
+
+        // Switching workspaces restores the start of each one
+        let later = now + Duration::from_secs(60);
+        assert_eq!(
+            restore_workspace_from(&path, "/home/user/a", grace, later),
+            Some(start_a)
+        );
+        assert_eq!(
+            restore_workspace_from(&path, "/home/user/b", grace, later),
+            Some(start_b)
+        );
+        assert_eq!(
+            restore_workspace_from(&path, "/home/user/c", grace, later),
+            None
+        );
+
+        let expired = now + grace + Duration::from_secs(1);
+        assert_eq!(
+            restore_workspace_from(&path, "/home/user/a", grace, expired),
+            None
+        );
+
+        // Expired workspaces are dropped on save
+        save_workspace_to(&path, "/home/user/c", start_b, grace, expired).unwrap();
+        assert_eq!(read_workspaces(&path).len(), 1);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_session_file() {
+        let path = temp_file("corrupt");
+        fs::write(&path, "yesterday").unwrap();
+
+        assert_eq!(restore_from(&path, Duration::from_secs(60)), None);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_start() {
+        let mut starts = Starts::default();
+        let (first, later) = (Duration::from_secs(100), Duration::from_secs(200));
+
+        starts.switch_file(Path::new("/project/a.rs"), first);
+        assert_eq!(starts.get(TimestampSource::File), first);
+
+        starts.switch_file(Path::new("/project/a.rs"), later);
+        assert_eq!(starts.get(TimestampSource::File), first);
+
+        starts.switch_file(Path::new("/project/b.rs"), later);
+        assert_eq!(starts.get(TimestampSource::File), later);
+    }
+}
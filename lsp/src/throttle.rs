@@ -0,0 +1,190 @@
+/*
+ * This file is part of discord-presence. Extension for Zed that adds support for Discord Rich Presence using LSP.
+ *
+ * Copyright (c) 2024 Steinhübl
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>
+ */
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// Runs at most one update per interval, an update during the interval replaces the pending
+/// one and runs once the interval is over so the latest state is never lost.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+    last_run: Arc<Mutex<Option<Instant>>>,
+    held_until: Arc<Mutex<Option<Instant>>>,
+    pending: Arc<Mutex<Option<JoinHandle<()>>>>, // only while it's waiting, never once it runs
+    running: Arc<Mutex<()>>,                     // held by the update being sent
+}
+
+impl Throttle {
+    /// Delays every update until `duration` from now, only the last one runs afterwards.
+    pub async fn hold(&self, duration: Duration) {
+        *self.held_until.lock().await = Some(Instant::now() + duration);
+    }
+
+    pub async fn run<F>(&self, interval: Duration, update: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut pending = self.pending.lock().await;
+        if let Some(handle) = pending.take() {
+            handle.abort();
+        }
+        // Aborting an update halfway could cut off the write to Discord, it finishes first
+        let running = self.running.lock().await;
+
+        let wait = self
+            .last_run
+            .lock()
+            .await
+            .map_or(Duration::ZERO, |last_run| {
+                interval.saturating_sub(last_run.elapsed())
+            });
+        let held = self
+            .held_until
+            .lock()
+            .await
+            .map_or(Duration::ZERO, |until| {
+                until.saturating_duration_since(Instant::now())
+            });
+        let wait = wait.max(held);
+
+        if wait.is_zero() {
+            *self.last_run.lock().await = Some(Instant::now());
+            drop(pending);
+            update.await;
+            drop(running);
+            return;
+        }
+        drop(running);
+
+        let last_run = Arc::clone(&self.last_run);
+        let own_pending = Arc::clone(&self.pending);
+        let own_running = Arc::clone(&self.running);
+        *pending = Some(tokio::spawn(async move {
+            time::sleep(wait).await;
+
+            // Can't be aborted from here on, as it's no longer pending
+            let mut pending = own_pending.lock().await;
+            pending.take();
+            let _running = own_running.lock().await;
+            drop(pending);
+
+            *last_run.lock().await = Some(Instant::now());
+            update.await;
+        }));
+    }
+
+    /// Drops the pending update, if any, an update already being sent still finishes
+    pub async fn cancel(&self) {
+        if let Some(handle) = self.pending.lock().await.take() {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_burst_runs_first_and_last() {
+        let throttle = Throttle::default();
+        let interval = Duration::from_millis(100);
+        let runs = Arc::new(StdMutex::new(Vec::new()));
+
+        for i in 0..5 {
+            let runs = Arc::clone(&runs);
+            throttle
+                .run(interval, async move { runs.lock().unwrap().push(i) })
+                .await;
+        }
+        assert_eq!(*runs.lock().unwrap(), vec![0]);
+
+        time::sleep(interval * 2).await;
+        assert_eq!(*runs.lock().unwrap(), vec![0, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_hold_runs_only_the_last() {
+        let throttle = Throttle::default();
+        let grace = Duration::from_millis(100);
+        let runs = Arc::new(StdMutex::new(Vec::new()));
+        throttle.hold(grace).await;
+
+        for i in 0..5 {
+            let runs = Arc::clone(&runs);
+            throttle
+                .run(Duration::ZERO, async move { runs.lock().unwrap().push(i) })
+                .await;
+        }
+        assert!(runs.lock().unwrap().is_empty());
+
+        time::sleep(grace * 2).await;
+        assert_eq!(*runs.lock().unwrap(), vec![4]);
+    }
+
+    #[tokio::test]
+    async fn test_no_interval_runs_everything() {
+        let throttle = Throttle::default();
+        let runs = Arc::new(StdMutex::new(Vec::new()));
+
+        for i in 0..3 {
+            let runs = Arc::clone(&runs);
+            throttle
+                .run(Duration::ZERO, async move { runs.lock().unwrap().push(i) })
+                .await;
+        }
+
+        assert_eq!(*runs.lock().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_running_update_is_not_aborted() {
+        let throttle = Throttle::default();
+        let interval = Duration::from_millis(50);
+        let runs = Arc::new(StdMutex::new(Vec::new()));
+
+        throttle.run(interval, async {}).await;
+        let first = Arc::clone(&runs);
+        throttle
+            .run(interval, async move {
+                first.lock().unwrap().push("started");
+                time::sleep(Duration::from_millis(100)).await;
+                first.lock().unwrap().push("finished");
+            })
+            .await;
+
+        // The trailing update is being sent by now
+        time::sleep(interval * 2).await;
+        throttle.cancel().await;
+        let second = Arc::clone(&runs);
+        throttle
+            .run(interval, async move { second.lock().unwrap().push("next") })
+            .await;
+
+        time::sleep(interval * 4).await;
+        assert_eq!(*runs.lock().unwrap(), vec!["started", "finished", "next"]);
+    }
+}
@@ -1,52 +1,271 @@
-use crate::{configuration::Configuration, languages::get_language, Document};
-
-macro_rules! replace_with_capitalization {
-    ($text:expr, $($placeholder:expr => $value:expr),*) => {{
-        let mut result = $text.to_string();
-        $(
-            let capitalized = capitalize_first_letter($value);
-            result = result.replace(concat!("{", $placeholder, "}"), $value)
-                           .replace(concat!("{", $placeholder, ":u}"), &capitalized);
-        )*
-        result
-    }};
-}
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::{
+    configuration::{Configuration, PrivacyMode},
+    git::GitStatus,
+    languages::document_language,
+    Document,
+};
+
+/// Stands in for the git status when names are hidden
+static NO_GIT_STATUS: GitStatus = GitStatus {
+    branch: None,
+    ahead_behind: None,
+};
 
 pub struct Placeholders<'a> {
     filename: Option<String>,
     workspace: &'a str,
     language: Option<String>,
     base_icons_url: &'a str,
+    git: &'a GitStatus,
+    elapsed: Option<Duration>,
+    project_language: Option<&'a str>,
+    dirty: bool,
 }
 
 impl<'a> Placeholders<'a> {
-    pub fn new(doc: Option<&'a Document>, config: &'a Configuration, workspace: &'a str) -> Self {
+    pub fn new(
+        doc: Option<&'a Document>,
+        config: &'a Configuration,
+        workspace: &'a str,
+        git: &'a GitStatus,
+    ) -> Self {
         let (filename, language) = if let Some(doc) = doc {
-            (Some(doc.get_filename()), Some(get_language(doc)))
+            let filename = doc.get_filename();
+            let filename = if config.hide_file_extension {
+                strip_extension(&filename)
+            } else {
+                filename
+            };
+            let filename = match config.max_filename_length {
+                Some(max_length) => truncate_middle(&filename, max_length),
+                None => filename,
+            };
+
+            (Some(filename), Some(document_language(doc, config)))
         } else {
             (None, None)
         };
 
-        Self {
+        let placeholders = Self {
             filename,
             workspace,
             language,
             base_icons_url: &config.base_icons_url,
+            git,
+            elapsed: None,
+            project_language: None,
+            dirty: doc.is_some_and(|doc| doc.dirty),
+        };
+
+        if config.privacy.mode == PrivacyMode::LanguageOnly {
+            placeholders.language_only()
+        } else {
+            placeholders
+        }
+    }
+
+    /// Drops everything that could name the file or project, only the language is kept.
+    fn language_only(self) -> Self {
+        Self {
+            filename: None,
+            workspace: "",
+            git: &NO_GIT_STATUS,
+            ..self
         }
     }
 
+    /// Sets the time since the session started for `{elapsed}`.
+    pub fn with_elapsed(mut self, elapsed: Duration) -> Self {
+        self.elapsed = Some(elapsed);
+        self
+    }
+
+    /// Sets the most common language of the workspace for `{project_language}`.
+    pub fn with_project_language(mut self, project_language: Option<&'a str>) -> Self {
+        self.project_language = project_language;
+        self
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    /// Replaces the placeholders in a single pass, values are borrowed unless a modifier changes them.
     pub fn replace(&self, text: &str) -> String {
-        let filename = self.filename.as_deref().unwrap_or("filename");
-        let language = self.language.as_deref().unwrap_or("language");
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+
+            let resolved = closing_brace(after)
+                .and_then(|end| Some((self.resolve(&after[..end])?, &after[end + 1..])));
 
-        replace_with_capitalization!(
-            text,
-            "filename" => filename,
-            "workspace" => self.workspace,
-            "language" => language,
-            "base_icons_url" => self.base_icons_url
-        )
+            if let Some((value, next)) = resolved {
+                result.push_str(&value);
+                rest = next;
+            } else {
+                // Not a placeholder, keep the brace and continue right after it
+                result.push('{');
+                rest = after;
+            }
+        }
+
+        result.push_str(rest);
+        result
     }
+
+    /// Resolves a placeholder with its modifiers and fallback, e.g. `filename:u:20|untitled`.
+    /// The fallback is used when the value is empty or missing and may contain placeholders.
+    fn resolve(&self, placeholder: &str) -> Option<Cow<'_, str>> {
+        let (placeholder, fallback) = match placeholder.split_once('|') {
+            Some((placeholder, fallback)) => (placeholder, Some(fallback)),
+            None => (placeholder, None),
+        };
+
+        let (name, modifiers) = match placeholder.split_once(':') {
+            Some((name, modifiers)) => (name, Some(modifiers)),
+            None => (placeholder, None),
+        };
+        let entry = PLACEHOLDERS.iter().find(|entry| entry.name == name)?;
+
+        if !entry.modifiers && modifiers.is_some() {
+            return None;
+        }
+
+        let value = (entry.resolve)(self).filter(|value| !value.is_empty());
+        let missing = value.is_none();
+        let mut value = value.unwrap_or(Cow::Borrowed(entry.missing));
+
+        for modifier in modifiers.into_iter().flat_map(|m| m.split(':')) {
+            value = Cow::Owned(apply_modifier(&value, modifier)?);
+        }
+
+        match fallback {
+            Some(fallback) if missing => Some(Cow::Owned(self.replace(fallback))),
+            _ => {
+                if missing {
+                    debug!(
+                        "{{{name}}} is unavailable as {}, add a fallback like {{{name}|...}} to replace it",
+                        entry.unavailable
+                    );
+                }
+
+                Some(value)
+            }
+        }
+    }
+}
+
+/// A placeholder resolving to `None` when its value isn't available
+struct Placeholder {
+    name: &'static str,
+    resolve: for<'a> fn(&'a Placeholders) -> Option<Cow<'a, str>>,
+    missing: &'static str, // shown when the value isn't available and there's no fallback
+    unavailable: &'static str, // why the value isn't available, for debugging templates
+    modifiers: bool,
+}
+
+const PLACEHOLDERS: &[Placeholder] = &[
+    Placeholder {
+        name: "filename",
+        resolve: |p| p.filename.as_deref().map(Cow::Borrowed),
+        missing: "filename",
+        unavailable: "no file is open, or it's hidden by the rules or `privacy.mode`",
+        modifiers: true,
+    },
+    Placeholder {
+        name: "workspace",
+        resolve: |p| Some(Cow::Borrowed(p.workspace)),
+        missing: "",
+        unavailable: "the workspace isn't shown while idle or with `privacy.mode`",
+        modifiers: true,
+    },
+    Placeholder {
+        name: "language",
+        resolve: |p| p.language.as_deref().map(Cow::Borrowed),
+        missing: "language",
+        unavailable: "no file is open",
+        modifiers: true,
+    },
+    Placeholder {
+        name: "project_language",
+        resolve: |p| p.project_language.map(Cow::Borrowed),
+        missing: "",
+        unavailable: "the workspace has no recognized source files",
+        modifiers: true,
+    },
+    // Unavailable when saved, so a fallback can show that instead
+    Placeholder {
+        name: "dirty",
+        resolve: |p| p.dirty.then_some(Cow::Borrowed("*")),
+        missing: "",
+        unavailable: "the file has no unsaved changes",
+        modifiers: false,
+    },
+    Placeholder {
+        name: "base_icons_url",
+        resolve: |p| Some(Cow::Borrowed(p.base_icons_url)),
+        missing: "",
+        unavailable: "`base_icons_url` is empty",
+        modifiers: true,
+    },
+    Placeholder {
+        name: "git_branch",
+        resolve: |p| p.git.branch.as_deref().map(Cow::Borrowed),
+        missing: "",
+        unavailable: "the workspace isn't a git repository or it's hidden by `privacy.mode`",
+        modifiers: true,
+    },
+    // Counts are empty without an upstream branch
+    Placeholder {
+        name: "git_ahead",
+        resolve: |p| {
+            p.git
+                .ahead_behind
+                .map(|(ahead, _)| ahead.to_string().into())
+        },
+        missing: "",
+        unavailable: "the branch has no upstream",
+        modifiers: false,
+    },
+    Placeholder {
+        name: "git_behind",
+        resolve: |p| {
+            p.git
+                .ahead_behind
+                .map(|(_, behind)| behind.to_string().into())
+        },
+        missing: "",
+        unavailable: "the branch has no upstream",
+        modifiers: false,
+    },
+    Placeholder {
+        name: "elapsed",
+        resolve: |p| p.elapsed.map(|elapsed| format_elapsed(elapsed).into()),
+        missing: "",
+        unavailable: "the session start isn't known",
+        modifiers: false,
+    },
+];
+
+/// Applies a modifier, `None` when it isn't one
+fn apply_modifier(value: &str, modifier: &str) -> Option<String> {
+    Some(match modifier {
+        "u" => capitalize_first_letter(value),
+        "lo" => value.to_lowercase(),
+        "t" => title_case(value),
+        _ => truncate(value, modifier.parse().ok()?),
+    })
 }
 
 pub fn set_optional_field<'a, T, F>(mut obj: T, field: Option<&'a str>, setter: F) -> T
@@ -59,6 +278,144 @@ where
     obj
 }
 
+/// Finds the brace closing the one right before `text`, skipping nested pairs.
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Follows symlinks to the real path, keeping the path as it is when it can't be resolved,
+/// e.g. a broken symlink.
+pub fn resolve_symlinks(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Expands a leading `~` to the home directory and `$VAR`/`${VAR}` to environment variables,
+/// unknown variables are kept as they are.
+pub fn expand_path(path: &str) -> String {
+    expand_env(&expand_home(path), true)
+}
+
+/// Expands a leading `~` to the home directory.
+pub fn expand_home(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            match env::var("HOME").or_else(|_| env::var("USERPROFILE")) {
+                Ok(home) => format!("{home}{rest}"),
+                Err(_) => path.to_string(),
+            }
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// Expands `$VAR`/`${VAR}` to environment variables and `$$` to a literal `$`,
+/// unknown variables are kept as they are or removed without `keep_unknown`.
+pub fn expand_env(value: &str, keep_unknown: bool) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+            continue;
+        }
+
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some((name, after)) => (name, after),
+                None => ("", rest),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+
+        match env::var(name).ok() {
+            // Not a variable, e.g. `$ 5` or an unclosed `${`
+            _ if name.is_empty() => result.push('$'),
+            Some(value) => {
+                result.push_str(&value);
+                rest = after;
+            }
+            None if keep_unknown => result.push('$'),
+            None => rest = after,
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Returns the path inside `root` joined with `/`, `None` when it's outside of it.
+pub fn relative_path(path: &Path, root: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+
+    Some(
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// Matches a path against a glob where `*` and `?` don't cross separators and `**` matches
+/// any number of directories, trailing separators are ignored.
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[char], path: &[char]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+            ['*', '*', '/', rest @ ..] => {
+                matches(rest, path) || (1..=path.len()).any(|i| matches(&pattern[2..], &path[i..]))
+            }
+            ['*', '*', rest @ ..] => (0..=path.len()).any(|i| matches(rest, &path[i..])),
+            ['*', rest @ ..] => (0..=path.len())
+                .take_while(|&i| i == 0 || path[i - 1] != '/')
+                .any(|i| matches(rest, &path[i..])),
+            ['?', rest @ ..] => {
+                path.first().is_some_and(|&c| c != '/') && matches(rest, &path[1..])
+            }
+            [c, rest @ ..] => path.first() == Some(c) && matches(rest, &path[1..]),
+        }
+    }
+
+    let pattern: Vec<char> = pattern.trim_end_matches('/').chars().collect();
+    let path: Vec<char> = path.trim_end_matches('/').chars().collect();
+
+    matches(&pattern, &path)
+}
+
+/// Formats a duration like `1h 23m`, `5m` or `45s`, seconds are only shown below a minute.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let (hours, minutes) = (secs / 3600, secs / 60 % 60);
+
+    if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m")
+    } else {
+        format!("{secs}s")
+    }
+}
+
 fn capitalize_first_letter(s: &str) -> String {
     let mut c = s.chars();
     match c.next() {
@@ -66,3 +423,381 @@ fn capitalize_first_letter(s: &str) -> String {
         Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
     }
 }
+
+/// Capitalizes the first letter of every word, words are separated by whitespace, `-` and `_`.
+fn title_case(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut word_start = true;
+
+    for c in s.chars() {
+        if word_start {
+            result.extend(c.to_uppercase());
+        } else {
+            result.push(c);
+        }
+
+        word_start = c.is_whitespace() || c == '-' || c == '_';
+    }
+
+    result
+}
+
+/// Strips the last extension, dotfiles like `.bashrc` are kept as they are.
+fn strip_extension(filename: &str) -> String {
+    Path::new(filename)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(filename)
+        .to_string()
+}
+
+/// Truncates to at most `max_length` characters, ending with an ellipsis when shortened.
+fn truncate(s: &str, max_length: usize) -> String {
+    if s.chars().count() <= max_length {
+        return s.to_string();
+    }
+
+    if max_length == 0 {
+        return String::new();
+    }
+
+    let mut truncated: String = s.chars().take(max_length - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Truncates a filename in the middle, keeping its start and extension, e.g. `very-long-…e.tsx`.
+/// Names without an extension, or with one taking most of the space, are truncated at the end.
+fn truncate_middle(filename: &str, max_length: usize) -> String {
+    let chars: Vec<char> = filename.chars().collect();
+    if chars.len() <= max_length {
+        return filename.to_string();
+    }
+
+    let extension = Path::new(filename)
+        .extension()
+        .map_or(0, |e| e.to_string_lossy().chars().count() + 1);
+    // One character of the name stays before the extension
+    let tail = extension + 1;
+    let available = max_length.saturating_sub(1);
+
+    if extension == 0 || tail > available / 2 {
+        return truncate(filename, max_length);
+    }
+
+    let mut truncated: String = chars[..available - tail].iter().collect();
+    truncated.push('…');
+    truncated.extend(&chars[chars.len() - tail..]);
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Url;
+
+    use super::*;
+
+    #[test]
+    fn test_title_case_modifier() {
+        let config = Configuration::new();
+        let git = GitStatus::default();
+        let placeholders = Placeholders::new(None, &config, "my-cool project", &git);
+
+        assert_eq!(placeholders.replace("{workspace:t}"), "My-Cool Project");
+        assert_eq!(placeholders.replace("{workspace:u}"), "My-cool project");
+    }
+
+    #[test]
+    fn test_title_case() {
+        assert_eq!(title_case("snake_case  name"), "Snake_Case  Name");
+        assert_eq!(title_case(""), "");
+    }
+
+    #[test]
+    fn test_truncation_modifier() {
+        let config = Configuration::new();
+        let git = GitStatus::default();
+        let placeholders = Placeholders::new(None, &config, "workspace", &git);
+
+        assert_eq!(placeholders.replace("{workspace:9}"), "workspace");
+        assert_eq!(placeholders.replace("{workspace:5}"), "work…");
+        assert_eq!(placeholders.replace("{workspace:u:5}"), "Work…");
+        assert_eq!(placeholders.replace("{workspace:x}"), "{workspace:x}");
+        assert_eq!(placeholders.replace("{{workspace}}"), "{workspace}");
+    }
+
+    #[test]
+    fn test_max_filename_length() {
+        let mut config = Configuration::new();
+        config.max_filename_length = Some(16);
+        let git = GitStatus::default();
+
+        for (url, filename) in [
+            ("file:///home/user/main.rs", "main.rs"),
+            ("file:///home/user/sixteen-chars.rs", "sixteen-chars.rs"),
+            (
+                "file:///home/user/very-long-component-name.tsx",
+                "very-long-…e.tsx",
+            ),
+            ("file:///home/user/a-very-long-makefile", "a-very-long-mak…"),
+        ] {
+            let document = Document::new(Url::parse(url).unwrap());
+            let placeholders = Placeholders::new(Some(&document), &config, "workspace", &git);
+
+            assert_eq!(placeholders.replace("{filename}"), filename);
+        }
+    }
+
+    #[test]
+    fn test_truncate_middle_multibyte() {
+        assert_eq!(
+            truncate_middle("příliš-žluťoučký-kůň.rs", 13),
+            "příliš-ž…ň.rs"
+        );
+        assert_eq!(truncate_middle("日本語のファイル.md", 9), "日本語の…ル.md");
+        assert_eq!(truncate_middle("component.stories", 10), "component…");
+        assert_eq!(truncate_middle("main.rs", 0), "");
+    }
+
+    #[test]
+    fn test_truncate_multibyte() {
+        assert_eq!(truncate("žluťoučký", 9), "žluťoučký");
+        assert_eq!(truncate("žluťoučký", 5), "žluť…");
+        assert_eq!(truncate("日本語のファイル", 3), "日本…");
+        assert_eq!(truncate("abc", 0), "");
+    }
+
+    #[test]
+    fn test_git_placeholders() {
+        let config = Configuration::new();
+        let git = GitStatus {
+            branch: Some(String::from("main")),
+            ahead_behind: Some((3, 1)),
+        };
+        let placeholders = Placeholders::new(None, &config, "workspace", &git);
+
+        assert_eq!(
+            placeholders.replace("{git_branch}: {git_ahead} ahead, {git_behind} behind"),
+            "main: 3 ahead, 1 behind"
+        );
+
+        let git = GitStatus::default();
+        let placeholders = Placeholders::new(None, &config, "workspace", &git);
+        assert_eq!(placeholders.replace("[{git_ahead}{git_behind}]"), "[]");
+    }
+
+    #[test]
+    fn test_hide_file_extension() {
+        let mut config = Configuration::new();
+        config.hide_file_extension = true;
+        let git = GitStatus::default();
+
+        for (url, filename) in [
+            ("file:///home/user/main.rs", "main"),
+            ("file:///home/user/.bashrc", ".bashrc"),
+            ("file:///home/user/archive.tar.gz", "archive.tar"),
+        ] {
+            let document = Document::new(Url::parse(url).unwrap());
+            let placeholders = Placeholders::new(Some(&document), &config, "workspace", &git);
+
+            assert_eq!(placeholders.replace("{filename}"), filename);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_symlinks() {
+        let dir = env::temp_dir().join("discord-presence-lsp-test-symlinks");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("project")).unwrap();
+        std::os::unix::fs::symlink(dir.join("project"), dir.join("link")).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), dir.join("broken")).unwrap();
+
+        let real = resolve_symlinks(&dir.join("project"));
+        assert_eq!(resolve_symlinks(&dir.join("link")), real);
+        assert_eq!(resolve_symlinks(&dir.join("broken")), dir.join("broken"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_env() {
+        let _guard = crate::configuration::ENV_LOCK.blocking_lock();
+        env::set_var(
+            "DISCORD_PRESENCE_TEST_ICON_HOST",
+            "https://icons.example.com",
+        );
+
+        for keep_unknown in [true, false] {
+            assert_eq!(
+                expand_env("$DISCORD_PRESENCE_TEST_ICON_HOST/icons", keep_unknown),
+                "https://icons.example.com/icons"
+            );
+            assert_eq!(
+                expand_env("${DISCORD_PRESENCE_TEST_ICON_HOST}s", keep_unknown),
+                "https://icons.example.coms"
+            );
+            assert_eq!(expand_env("$$5 or $$HOME", keep_unknown), "$5 or $HOME");
+            assert_eq!(expand_env("$ and ${", keep_unknown), "$ and ${");
+        }
+
+        assert_eq!(
+            expand_env("[$DISCORD_PRESENCE_UNSET_VARIABLE]", true),
+            "[$DISCORD_PRESENCE_UNSET_VARIABLE]"
+        );
+        assert_eq!(
+            expand_env("[${DISCORD_PRESENCE_UNSET_VARIABLE}]", false),
+            "[]"
+        );
+
+        env::remove_var("DISCORD_PRESENCE_TEST_ICON_HOST");
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let home = env::var("HOME").unwrap();
+
+        assert_eq!(expand_path("~"), home);
+        assert_eq!(expand_path("~/sub"), format!("{home}/sub"));
+        assert_eq!(expand_path("$HOME/sub"), format!("{home}/sub"));
+        assert_eq!(expand_path("${HOME}/sub"), format!("{home}/sub"));
+        assert_eq!(expand_path("~user/sub"), "~user/sub");
+        assert_eq!(
+            expand_path("/$DISCORD_PRESENCE_UNSET_VARIABLE/sub"),
+            "/$DISCORD_PRESENCE_UNSET_VARIABLE/sub"
+        );
+        assert_eq!(expand_path("/costs/$5"), "/costs/$5");
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches(
+            "/home/user/work/**",
+            "/home/user/work/client/app"
+        ));
+        assert!(glob_matches("/home/user/work/*", "/home/user/work/client/"));
+        assert!(!glob_matches(
+            "/home/user/work/*",
+            "/home/user/work/client/app"
+        ));
+        assert!(glob_matches("/home/**/app", "/home/app"));
+        assert!(glob_matches("/home/**/app", "/home/user/work/app"));
+        assert!(glob_matches("/home/user/client-?", "/home/user/client-a"));
+        assert!(!glob_matches("/home/user/client-?", "/home/user/client-ab"));
+        assert!(glob_matches("/home/user/oss", "/home/user/oss"));
+        assert!(!glob_matches("/home/user/oss", "/home/user/oss-fork"));
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_secs(0)), "0s");
+        assert_eq!(format_elapsed(Duration::from_secs(45)), "45s");
+        assert_eq!(format_elapsed(Duration::from_secs(5 * 60 + 3)), "5m");
+        assert_eq!(format_elapsed(Duration::from_secs(3600)), "1h 0m");
+        assert_eq!(
+            format_elapsed(Duration::from_secs(3600 + 23 * 60 + 59)),
+            "1h 23m"
+        );
+        assert_eq!(format_elapsed(Duration::from_secs(26 * 3600)), "26h 0m");
+    }
+
+    #[test]
+    fn test_fallback() {
+        let config = Configuration::new();
+        let mut git = GitStatus {
+            branch: Some(String::from("feature")),
+            ahead_behind: None,
+        };
+
+        let placeholders = Placeholders::new(None, &config, "workspace", &git);
+        assert_eq!(placeholders.replace("{git_branch|main}"), "feature");
+        assert_eq!(placeholders.replace("{git_branch:u|main}"), "Feature");
+        assert_eq!(placeholders.replace("{git_ahead|0}"), "0");
+        assert_eq!(placeholders.replace("{filename|untitled}"), "untitled");
+        assert_eq!(placeholders.replace("{filename}"), "filename");
+
+        git.branch = Some(String::new());
+        let placeholders = Placeholders::new(None, &config, "workspace", &git);
+        assert_eq!(placeholders.replace("{git_branch|main}"), "main");
+
+        git.branch = None;
+        let placeholders = Placeholders::new(None, &config, "workspace", &git);
+        assert_eq!(placeholders.replace("{git_branch|main}"), "main");
+        assert_eq!(placeholders.replace("{git_branch|}"), "");
+        assert_eq!(
+            placeholders.replace("On {git_branch|{workspace:u}}"),
+            "On Workspace"
+        );
+        assert_eq!(placeholders.replace("{unknown|main}"), "{unknown|main}");
+    }
+
+    /// Times rendering the six default fields, to compare changes to `replace` before and after:
+    /// `cargo test --release -- --ignored --nocapture bench_replace_default_fields`
+    #[test]
+    #[ignore]
+    fn bench_replace_default_fields() {
+        let config = Configuration::new();
+        let git = GitStatus {
+            branch: Some(String::from("main")),
+            ahead_behind: None,
+        };
+        let document = Document::new(Url::parse("file:///home/user/main.rs").unwrap());
+        let placeholders = Placeholders::new(Some(&document), &config, "my-project", &git);
+        let fields = [
+            &config.state,
+            &config.details,
+            &config.large_image,
+            &config.large_text,
+            &config.small_image,
+            &config.small_text,
+        ];
+
+        let start = std::time::Instant::now();
+        for _ in 0..200_000 {
+            for field in fields.iter().copied().flatten() {
+                std::hint::black_box(placeholders.replace(std::hint::black_box(field)));
+            }
+        }
+        println!(
+            "Rendered the default fields 200000 times in {:?}",
+            start.elapsed()
+        );
+    }
+
+    #[test]
+    fn test_placeholders_parity() {
+        let config = Configuration::new();
+        let git = GitStatus {
+            branch: Some(String::from("main")),
+            ahead_behind: Some((2, 0)),
+        };
+        let document = Document::new(Url::parse("file:///home/user/Main.rs").unwrap());
+        let placeholders = Placeholders::new(Some(&document), &config, "my-project", &git);
+
+        for (text, expected) in [
+            ("{filename}", "Main.rs"),
+            ("{filename:lo}", "main.rs"),
+            ("{workspace:t}", "My-Project"),
+            ("{language:u}", "Rust"),
+            ("{git_branch:u:3}", "Ma…"),
+            ("{git_ahead}/{git_behind}", "2/0"),
+            ("{git_ahead:u}", "{git_ahead:u}"),
+            ("{base_icons_url}", config.base_icons_url.as_str()),
+            ("{elapsed|just now}", "just now"),
+        ] {
+            assert_eq!(placeholders.replace(text), expected);
+        }
+
+        let git = GitStatus::default();
+        let placeholders = Placeholders::new(None, &config, "my-project", &git);
+
+        for (text, expected) in [
+            ("{filename}", "filename"),
+            ("{language:u}", "Language"),
+            ("{git_branch}", ""),
+            ("{git_ahead}", ""),
+        ] {
+            assert_eq!(placeholders.replace(text), expected);
+        }
+    }
+}
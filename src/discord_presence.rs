@@ -17,14 +17,165 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>
  */
 
+use std::collections::hash_map::DefaultHasher;
 use std::fs;
-use zed_extension_api::{self as zed};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zed_extension_api::{self as zed, settings::LspSettings};
+
+const DEFAULT_REPOSITORY: &str = "xhyrom/zed-discord-presence";
+
+/// Caches the GitHub release lookup, anonymous requests are rate limited
+const RELEASE_CACHE_FILE: &str = "release-cache";
+#[allow(clippy::duration_suboptimal_units)] // `Duration::from_hours` needs Rust 1.91
+const RELEASE_CACHE_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Binaries shipped with the extension for offline installs, e.g. `bin/x86_64-unknown-linux-gnu`
+const BUNDLED_DIR: &str = "bin";
+
+struct CachedRelease {
+    repository: String,
+    version: String,
+    download_url: String,
+    fetched_at: u64,
+}
+
+impl CachedRelease {
+    fn read() -> Option<Self> {
+        let contents = fs::read_to_string(RELEASE_CACHE_FILE).ok()?;
+        let mut lines = contents.lines();
+
+        Some(Self {
+            repository: lines.next()?.to_string(),
+            version: lines.next()?.to_string(),
+            download_url: lines.next()?.to_string(),
+            fetched_at: lines.next()?.parse().ok()?,
+        })
+    }
+
+    fn write(&self) {
+        let contents = format!(
+            "{}\n{}\n{}\n{}\n",
+            self.repository, self.version, self.download_url, self.fetched_at
+        );
+        fs::write(RELEASE_CACHE_FILE, contents).ok();
+    }
+
+    fn is_fresh(&self) -> bool {
+        now().saturating_sub(self.fetched_at) < RELEASE_CACHE_TTL.as_secs()
+    }
+}
+
+/// Read from `lsp.discord_presence.settings.binary`, as the `binary` settings Zed knows about
+/// can't hold extra fields.
+struct BinarySettings {
+    repository: String,           // GitHub `owner/name` to download the releases from
+    download_url: Option<String>, // mirror of the release assets
+}
+
+impl Default for BinarySettings {
+    fn default() -> Self {
+        Self {
+            repository: DEFAULT_REPOSITORY.to_string(),
+            download_url: None,
+        }
+    }
+}
+
+impl BinarySettings {
+    fn for_worktree(worktree: &zed::Worktree) -> zed::Result<Self> {
+        let binary = LspSettings::for_worktree("discord_presence", worktree)
+            .ok()
+            .and_then(|settings| settings.settings)
+            .and_then(|settings| settings.get("binary").cloned());
+        let Some(binary) = binary else {
+            return Ok(Self::default());
+        };
+
+        let repository = match binary.get("repository").and_then(|r| r.as_str()) {
+            Some(repository) if is_repository_slug(repository) => repository.to_string(),
+            Some(repository) => {
+                return Err(format!(
+                    "invalid binary.repository {repository:?}, expected `owner/name`"
+                ))
+            }
+            None => DEFAULT_REPOSITORY.to_string(),
+        };
+
+        Ok(Self {
+            repository,
+            download_url: binary
+                .get("download_url")
+                .and_then(|url| url.as_str())
+                .filter(|url| !url.is_empty())
+                .map(|url| url.trim_end_matches('/').to_string()),
+        })
+    }
+}
+
+fn is_repository_slug(slug: &str) -> bool {
+    let is_part = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+
+    slug.split_once('/')
+        .is_some_and(|(owner, name)| is_part(owner) && is_part(name))
+}
+
+fn mirror_version(url: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+
+    format!("mirror-{:x}", hasher.finish())
+}
+
+/// The target triple of the release assets for the current platform
+#[allow(clippy::match_wildcard_for_single_variants)]
+fn target() -> zed::Result<String> {
+    let (platform, arch) = zed::current_platform();
+
+    Ok(format!(
+        "{arch}-{os}",
+        arch = match arch {
+            zed::Architecture::Aarch64 => "aarch64",
+            zed::Architecture::X8664 => "x86_64",
+            _ => return Err(format!("unsupported architecture: {arch:?}")),
+        },
+        os = match platform {
+            zed::Os::Mac => "apple-darwin",
+            zed::Os::Linux => "unknown-linux-gnu",
+            zed::Os::Windows => "pc-windows-msvc",
+        },
+    ))
+}
+
+/// Marks the binary as executable, which Windows decides by the file extension instead
+fn make_executable(path: &str) -> zed::Result<()> {
+    if zed::current_platform().0 == zed::Os::Windows {
+        return Ok(());
+    }
+
+    zed::make_file_executable(path).map_err(|e| {
+        format!(
+            "failed to make {path} executable, check that the extension directory is writable \
+             and not mounted with `noexec`: {e}"
+        )
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
 
 struct DiscordPresenceExtension {
     cached_binary_path: Option<String>,
 }
 
-#[allow(clippy::match_wildcard_for_single_variants)]
 impl DiscordPresenceExtension {
     fn language_server_binary_path(
         &mut self,
@@ -36,64 +187,116 @@ impl DiscordPresenceExtension {
         }
 
         if let Some(path) = &self.cached_binary_path {
-            if fs::metadata(path).map_or(false, |stat| stat.is_file()) {
+            if fs::metadata(path).is_ok_and(|stat| stat.is_file()) {
                 return Ok(path.clone());
             }
         }
 
+        // A bundled binary that can't be made executable is reported if installing fails too
+        let bundled_error = match Self::bundled_binary() {
+            Ok(Some(path)) => {
+                self.cached_binary_path = Some(path.clone());
+                return Ok(path);
+            }
+            Ok(None) => format!("no bundled binary in {BUNDLED_DIR}/"),
+            Err(e) => e,
+        };
+
+        let binary_path = BinarySettings::for_worktree(worktree)
+            .and_then(|settings| Self::install_binary(language_server_id, &settings))
+            .map_err(|e| format!("{bundled_error} and installing one failed: {e}"))
+            .inspect_err(|e| {
+                zed::set_language_server_installation_status(
+                    language_server_id,
+                    &zed::LanguageServerInstallationStatus::Failed(e.clone()),
+                );
+            })?;
+
         zed::set_language_server_installation_status(
             language_server_id,
-            &zed_extension_api::LanguageServerInstallationStatus::CheckingForUpdate,
+            &zed::LanguageServerInstallationStatus::None,
         );
 
-        let release = zed::latest_github_release(
-            "xhyrom/zed-discord-presence",
-            zed::GithubReleaseOptions {
-                require_assets: true,
-                pre_release: false,
-            },
-        )?;
+        self.cached_binary_path = Some(binary_path.clone());
+        Ok(binary_path)
+    }
+
+    /// The binary shipped in `bin/<target>/` with the extension, checked before downloading
+    fn bundled_binary() -> zed::Result<Option<String>> {
+        let Ok(target) = target() else {
+            return Ok(None);
+        };
+        let path = format!("{BUNDLED_DIR}/{target}/discord-presence-lsp");
+        if !fs::metadata(&path).is_ok_and(|stat| stat.is_file()) {
+            return Ok(None);
+        }
 
-        let (platform, arch) = zed::current_platform();
+        // Archives don't always keep the permissions
+        make_executable(&path)?;
+        Ok(Some(path))
+    }
+
+    /// Downloads the latest release unless it's already installed, reporting the progress to Zed
+    fn install_binary(
+        language_server_id: &zed::LanguageServerId,
+        settings: &BinarySettings,
+    ) -> zed::Result<String> {
+        zed::set_language_server_installation_status(
+            language_server_id,
+            &zed_extension_api::LanguageServerInstallationStatus::CheckingForUpdate,
+        );
+
+        let (platform, _) = zed::current_platform();
         let asset_name = format!(
-            "discord-presence-lsp-{arch}-{os}.{extension}",
-            arch = match arch {
-                zed::Architecture::Aarch64 => "aarch64",
-                zed::Architecture::X8664 => "x86_64",
-                _ => return Err(format!("unsupported architecture: {arch:?}")),
-            },
-            os = match platform {
-                zed::Os::Mac => "apple-darwin",
-                zed::Os::Linux => "unknown-linux-gnu",
-                zed::Os::Windows => "pc-windows-msvc",
-            },
+            "discord-presence-lsp-{target}.{extension}",
+            target = target()?,
             extension = match platform {
                 zed::Os::Mac | zed::Os::Linux => "tar.gz",
                 zed::Os::Windows => "zip",
             }
         );
 
-        let asset = release
-            .assets
-            .iter()
-            .find(|asset| asset.name == asset_name)
-            .ok_or_else(|| format!("no asset found matching {asset_name:?}"))?;
+        let cached = CachedRelease::read();
+        let release = match (&settings.download_url, cached) {
+            // A mirror has no version to check, a different url is installed separately
+            (Some(url), _) => CachedRelease {
+                repository: settings.repository.clone(),
+                version: mirror_version(url),
+                download_url: format!("{url}/{asset_name}"),
+                fetched_at: now(),
+            },
+            (None, Some(cached))
+                if cached.is_fresh() && cached.repository == settings.repository =>
+            {
+                cached
+            }
+            (None, cached) => match Self::fetch_release(&settings.repository, &asset_name) {
+                Ok(release) => {
+                    release.write();
+                    release
+                }
+                // Keep using the last known release when GitHub can't be reached
+                Err(e) => cached
+                    .filter(|cached| cached.repository == settings.repository)
+                    .ok_or(e)?,
+            },
+        };
 
         let version_dir = format!("discord-presence-lsp-{}", release.version);
-        let asset_name = asset_name
-            .split('.')
-            .next()
-            .expect("failed to split asset name");
-        let binary_path: String = format!("{version_dir}/{asset_name}/discord-presence-lsp");
+        let asset_stem = asset_name
+            .split_once('.')
+            .map(|(stem, _)| stem)
+            .ok_or_else(|| format!("asset name {asset_name:?} has no extension"))?;
+        let binary_path: String = format!("{version_dir}/{asset_stem}/discord-presence-lsp");
 
-        if !fs::metadata(&binary_path).map_or(false, |stat| stat.is_file()) {
+        if !fs::metadata(&binary_path).is_ok_and(|stat| stat.is_file()) {
             zed::set_language_server_installation_status(
                 language_server_id,
                 &zed::LanguageServerInstallationStatus::Downloading,
             );
 
             zed::download_file(
-                &asset.download_url,
+                &release.download_url,
                 &version_dir,
                 match platform {
                     zed::Os::Mac | zed::Os::Linux => zed::DownloadedFileType::GzipTar,
@@ -102,21 +305,60 @@ impl DiscordPresenceExtension {
             )
             .map_err(|e| format!("failed to download file: {e}"))?;
 
-            zed::make_file_executable(&binary_path).expect("failed to make file executable");
+            if !fs::metadata(&binary_path).is_ok_and(|stat| stat.is_file()) {
+                return Err(format!(
+                    "{binary_path} is missing from the downloaded archive"
+                ));
+            }
+
+            make_executable(&binary_path)?;
 
             let entries =
                 fs::read_dir(".").map_err(|e| format!("failed to list working directory {e}"))?;
             for entry in entries {
                 let entry = entry.map_err(|e| format!("failed to load directory entry {e}"))?;
-                if entry.file_name().to_str() != Some(&version_dir) {
+                let name = entry.file_name();
+                if ![version_dir.as_str(), RELEASE_CACHE_FILE, BUNDLED_DIR]
+                    .iter()
+                    .any(|keep| name.to_str() == Some(keep))
+                {
                     fs::remove_dir_all(entry.path()).ok();
                 }
             }
         }
 
-        self.cached_binary_path = Some(binary_path.clone());
         Ok(binary_path)
     }
+
+    fn fetch_release(repository: &str, asset_name: &str) -> zed::Result<CachedRelease> {
+        let release = zed::latest_github_release(
+            repository,
+            zed::GithubReleaseOptions {
+                require_assets: true,
+                pre_release: false,
+            },
+        )?;
+
+        let asset = release
+            .assets
+            .iter()
+            .find(|asset| asset.name == asset_name)
+            .ok_or_else(|| format!("no asset found matching {asset_name:?}"))?;
+
+        // Forks may reuse the upstream tags, so they're installed separately
+        let version = if repository == DEFAULT_REPOSITORY {
+            release.version
+        } else {
+            format!("{}-{}", repository.replace('/', "-"), release.version)
+        };
+
+        Ok(CachedRelease {
+            repository: repository.to_string(),
+            version,
+            download_url: asset.download_url.clone(),
+            fetched_at: now(),
+        })
+    }
 }
 
 impl zed::Extension for DiscordPresenceExtension {